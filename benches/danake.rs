@@ -5,13 +5,15 @@ use merlin::Transcript;
 use rand;
 
 use danake;
+use danake::nullifier::MemoryNullifierStore;
+use danake::{IssuerContext, SystemClock};
 
-// Only do one benchmark for now so we can move things around later.
 pub fn wallet_topup_response(c: &mut Criterion) {
-    use danake::{wallet::*, EpochParameters};
+    use danake::{wallet::*, DeploymentId, EpochParameters};
 
     let epoch_params = EpochParameters::from(std::time::Duration::from_secs(86400));
     let epoch = epoch_params.epoch_at(chrono::Utc::now());
+    let deployment = DeploymentId::new("bench", epoch_params, epoch_params, 32);
 
     let secret = Secrets::new(epoch, rand::thread_rng());
     let params = Parameters::from(&secret);
@@ -19,6 +21,7 @@ pub fn wallet_topup_response(c: &mut Criterion) {
     let (client_state, request) = Wallet::request_issuance(
         1_000,
         &params,
+        &deployment,
         Transcript::new(b"wallet issuance test"),
         rand::thread_rng(),
     );
@@ -26,6 +29,7 @@ pub fn wallet_topup_response(c: &mut Criterion) {
     let response = secret
         .issue(
             request,
+            &mut IssuerContext::new(&deployment, &SystemClock, &mut MemoryNullifierStore::new()),
             Transcript::new(b"wallet issuance test"),
             rand::thread_rng(),
         )
@@ -39,6 +43,7 @@ pub fn wallet_topup_response(c: &mut Criterion) {
         .request_topup(
             2_000,
             &params,
+            &deployment,
             Transcript::new(b"wallet topup test"),
             rand::thread_rng(),
         )
@@ -49,6 +54,11 @@ pub fn wallet_topup_response(c: &mut Criterion) {
             let _response = secret
                 .topup(
                     request.clone(),
+                    &mut IssuerContext::new(
+                        &deployment,
+                        &danake::SystemClock,
+                        &mut MemoryNullifierStore::new(),
+                    ),
                     Transcript::new(b"wallet topup test"),
                     rand::thread_rng(),
                 )
@@ -57,5 +67,104 @@ pub fn wallet_topup_response(c: &mut Criterion) {
     });
 }
 
-criterion_group!(wallet_issuance, wallet_topup_response);
-criterion_main!(wallet_issuance);
+// Token spends are the most common presentation, so benchmark the issuer's
+// work for each supported token rangeproof size.
+pub fn token_spend_response(c: &mut Criterion) {
+    use danake::{token, wallet, DeploymentId, EpochParameters};
+
+    let epoch_params = EpochParameters::from(std::time::Duration::from_secs(86400));
+    let epoch = epoch_params.epoch_at(chrono::Utc::now());
+    let deployment = DeploymentId::new("bench", epoch_params, epoch_params, 32);
+
+    let wallet_secret = wallet::Secrets::new(epoch, rand::thread_rng());
+    let wallet_params = wallet::Parameters::from(&wallet_secret);
+
+    for &bits in &[16, 32] {
+        let token_secret = token::Secrets::new(epoch, bits, rand::thread_rng());
+        let token_params = token::Parameters::from(&token_secret);
+
+        let (client_state, request) = wallet::Wallet::request_issuance(
+            1_000,
+            &wallet_params,
+            &deployment,
+            Transcript::new(b"wallet issuance test"),
+            rand::thread_rng(),
+        );
+
+        let response = wallet_secret
+            .issue(
+                request,
+                &mut IssuerContext::new(
+                    &deployment,
+                    &SystemClock,
+                    &mut MemoryNullifierStore::new(),
+                ),
+                Transcript::new(b"wallet issuance test"),
+                rand::thread_rng(),
+            )
+            .expect("issuance should succeed");
+
+        let wallet = client_state
+            .verify_response(response)
+            .expect("response should verify");
+
+        let (client_state, request) = wallet
+            .request_token_purchase(
+                100,
+                &wallet_params,
+                &token_params,
+                &deployment,
+                Transcript::new(b"token purchase test"),
+                rand::thread_rng(),
+            )
+            .expect("purchase request should succeed");
+
+        let response = request
+            .purchase(
+                &wallet_secret,
+                &token_secret,
+                &mut IssuerContext::new(
+                    &deployment,
+                    &danake::SystemClock,
+                    &mut MemoryNullifierStore::new(),
+                ),
+                Transcript::new(b"token purchase test"),
+                rand::thread_rng(),
+            )
+            .expect("purchase should succeed");
+
+        let (_wallet, token) = client_state
+            .verify_response(response)
+            .expect("response should verify");
+
+        let (_client_state, request) = token
+            .request_spend(
+                1,
+                &token_params,
+                &deployment,
+                Transcript::new(b"token spend test"),
+                rand::thread_rng(),
+            )
+            .expect("spend request should succeed");
+
+        c.bench_function(&format!("token spend request ({} bits)", bits), |b| {
+            b.iter(|| {
+                let _response = token_secret
+                    .spend(
+                        request.clone(),
+                        &mut IssuerContext::new(
+                            &deployment,
+                            &danake::SystemClock,
+                            &mut MemoryNullifierStore::new(),
+                        ),
+                        Transcript::new(b"token spend test"),
+                        rand::thread_rng(),
+                    )
+                    .expect("spend should succeed");
+            })
+        });
+    }
+}
+
+criterion_group!(danake_benches, wallet_topup_response, token_spend_response);
+criterion_main!(danake_benches);
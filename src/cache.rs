@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use merlin::Transcript;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{encoding, CredentialType, Epoch, Error, RolloverPolicy};
+
+/// A cache of the issuer's responses to presentations, keyed by the revealed
+/// nullifier.
+///
+/// If a response is lost in transit, the client has already spent its old
+/// credential.  A client which retries the identical request gets the cached
+/// response back instead of a nullifier reuse error, while a different
+/// request revealing the same nullifier is still rejected.  Since only the
+/// client holds the decryption key for the issued credential, returning the
+/// response to whoever replays the request reveals nothing new.
+///
+/// The cache is used by every issuer entry point which presents a
+/// credential, once it is added to the
+/// [`IssuerContext`](crate::IssuerContext) with
+/// [`with_cache`](crate::IssuerContext::with_cache).  Wallet issuance
+/// reveals no nullifier, so its responses are never cached.
+///
+/// Each epoch's entries are dropped as soon as credentials from that epoch
+/// can no longer be rolled over under the cache's [`RolloverPolicy`],
+/// according to the issuer's [`Clock`](crate::Clock).
+#[derive(Debug, Default)]
+pub struct ResponseCache {
+    entries: HashMap<(CredentialType, Epoch, [u8; 32]), Entry>,
+    policy: RolloverPolicy,
+}
+
+/// An encoded cached response, with the digest of the request it responds
+/// to.
+#[derive(Debug)]
+struct Entry {
+    digest: [u8; 32],
+    response: Vec<u8>,
+}
+
+impl ResponseCache {
+    /// Create an empty response cache.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Retain responses for as long as `policy` accepts rollovers.
+    pub fn with_rollover_policy(mut self, policy: RolloverPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// The number of cached responses.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Drop the responses for every epoch which has expired at `time`.
+    pub fn prune(&mut self, time: DateTime<Utc>) {
+        let policy = self.policy;
+        self.entries
+            .retain(|(_, epoch, _), _| !policy.has_expired_at(epoch, time));
+    }
+
+    /// Return the cached response to the encoded request `request` of the
+    /// protocol `label`, or compute it with `respond` and cache it if the
+    /// nullifier has no cached response.
+    pub(crate) fn get_or_respond<T, F>(
+        &mut self,
+        label: &'static [u8],
+        credential: CredentialType,
+        epoch: Epoch,
+        nullifier: [u8; 32],
+        request: &[u8],
+        respond: F,
+    ) -> Result<T, Error>
+    where
+        T: Serialize + DeserializeOwned,
+        F: FnOnce() -> Result<T, Error>,
+    {
+        let digest = digest(label, request);
+        if let Some(entry) = self.entries.get(&(credential, epoch, nullifier)) {
+            if entry.digest != digest {
+                return Err(Error::NullifierReuse(credential));
+            }
+            return encoding::from_bytes(&entry.response);
+        }
+        let response = respond()?;
+        let entry = Entry {
+            digest,
+            response: encoding::to_bytes(&response),
+        };
+        self.entries.insert((credential, epoch, nullifier), entry);
+        Ok(response)
+    }
+}
+
+/// Hash an encoded request of the protocol `label`.
+///
+/// Requests of different protocols which reveal the same nullifier have
+/// different digests, so a retried request only ever gets a response from
+/// the protocol it was sent to.
+fn digest(label: &[u8], request: &[u8]) -> [u8; 32] {
+    let mut transcript = Transcript::new(b"danake response cache");
+    transcript.append_message(b"protocol", label);
+    transcript.append_message(b"request", request);
+    let mut digest = [0u8; 32];
+    transcript.challenge_bytes(b"digest", &mut digest);
+    digest
+}
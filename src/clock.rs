@@ -0,0 +1,64 @@
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Duration, Utc};
+
+/// A source of the current time, used to determine epoch states.
+///
+/// Issuers which need a trusted time source can implement this trait
+/// themselves.
+pub trait Clock {
+    /// The current time.
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// A [`Clock`] which reads the system time.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A [`Clock`] which only moves when it is set or advanced.
+///
+/// Clones share the same time, so a clone can be given to each nullifier
+/// store and cache while the original is used to move them all through
+/// epochs.
+#[derive(Clone, Debug)]
+pub struct ManualClock {
+    time: Arc<Mutex<DateTime<Utc>>>,
+}
+
+impl ManualClock {
+    /// Create a clock stopped at `time`.
+    pub fn new(time: DateTime<Utc>) -> Self {
+        ManualClock {
+            time: Arc::new(Mutex::new(time)),
+        }
+    }
+
+    /// Set the clock to `time`.
+    pub fn set(&self, time: DateTime<Utc>) {
+        *self.time.lock().unwrap() = time;
+    }
+
+    /// Move the clock forward by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        let mut time = self.time.lock().unwrap();
+        *time = *time + duration;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.time.lock().unwrap()
+    }
+}
+
+impl<C: Clock> Clock for &C {
+    fn now(&self) -> DateTime<Utc> {
+        (**self).now()
+    }
+}
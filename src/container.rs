@@ -0,0 +1,70 @@
+use chacha20poly1305::aead::{Aead, NewAead, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use rand_core::{CryptoRng, RngCore};
+
+use crate::Error;
+
+/// The version of the sealed container format, sent as its first byte.
+const CONTAINER_VERSION: u8 = 1;
+
+const NONCE_LEN: usize = 24;
+
+/// Encrypt `plaintext` under `key` with a random nonce.
+///
+/// The container is bound to `label`, so that data sealed as one kind of
+/// object cannot be opened as another.
+pub(crate) fn seal<R: RngCore + CryptoRng>(
+    label: &'static [u8],
+    plaintext: &[u8],
+    key: &[u8; 32],
+    mut rng: R,
+) -> Vec<u8> {
+    let mut nonce = [0u8; NONCE_LEN];
+    rng.fill_bytes(&mut nonce);
+
+    let ciphertext = XChaCha20Poly1305::new(&Key::from(*key))
+        .encrypt(
+            &XNonce::from(nonce),
+            Payload {
+                msg: plaintext,
+                aad: &associated_data(label),
+            },
+        )
+        .expect("encryption cannot fail for in-memory buffers");
+
+    let mut sealed = Vec::with_capacity(1 + NONCE_LEN + ciphertext.len());
+    sealed.push(CONTAINER_VERSION);
+    sealed.extend_from_slice(&nonce);
+    sealed.extend_from_slice(&ciphertext);
+    sealed
+}
+
+/// Decrypt a container sealed with [`seal`] under the same `label` and `key`.
+pub(crate) fn open(label: &'static [u8], sealed: &[u8], key: &[u8; 32]) -> Result<Vec<u8>, Error> {
+    let (&version, rest) = sealed.split_first().ok_or(Error::Encoding)?;
+    if version != CONTAINER_VERSION {
+        return Err(Error::ContainerVersion(version));
+    }
+    if rest.len() < NONCE_LEN {
+        return Err(Error::Encoding);
+    }
+    let (nonce_bytes, ciphertext) = rest.split_at(NONCE_LEN);
+    let mut nonce = [0u8; NONCE_LEN];
+    nonce.copy_from_slice(nonce_bytes);
+
+    XChaCha20Poly1305::new(&Key::from(*key))
+        .decrypt(
+            &XNonce::from(nonce),
+            Payload {
+                msg: ciphertext,
+                aad: &associated_data(label),
+            },
+        )
+        .map_err(|_| Error::Decryption)
+}
+
+fn associated_data(label: &[u8]) -> Vec<u8> {
+    let mut aad = label.to_vec();
+    aad.push(CONTAINER_VERSION);
+    aad
+}
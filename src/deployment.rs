@@ -0,0 +1,119 @@
+use std::collections::BTreeSet;
+
+use merlin::Transcript;
+use serde::{Deserialize, Serialize};
+
+use crate::{token, wallet, EpochParameters, Error, PROTOCOL_VERSION};
+
+/// The identity of a Danake deployment, bound into every protocol
+/// transcript.
+///
+/// A deployment is identified by an operator-chosen name together with a
+/// hash of its parameter set: the wallet and token epoch durations and the
+/// token rangeproof size.  Clients and issuers of different deployments,
+/// such as staging and production, never accept each other's proofs, even
+/// if their keys collide or are shared by mistake.  Issuers reject secrets
+/// from a different parameter set with [`Error::DeploymentMismatch`], so the
+/// hash always describes the keys in use.
+///
+/// The deployment also fixes which protocol versions its issuer accepts.
+/// By default only [`PROTOCOL_VERSION`] is accepted, but an issuer can keep
+/// accepting older versions during a migration window while clients
+/// upgrade.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct DeploymentId {
+    name: String,
+    wallet_epochs: EpochParameters,
+    token_epochs: EpochParameters,
+    range_proof_bits: usize,
+    parameters_hash: [u8; 32],
+    accepted_versions: BTreeSet<u16>,
+}
+
+impl DeploymentId {
+    /// Identify the deployment called `name`, which uses the given wallet
+    /// and token epoch durations and token rangeproof size.
+    pub fn new(
+        name: &str,
+        wallet_epochs: EpochParameters,
+        token_epochs: EpochParameters,
+        range_proof_bits: usize,
+    ) -> Self {
+        let mut transcript = Transcript::new(b"danake deployment parameters");
+        transcript.append_u64(b"wallet_epochs", wallet_epochs.0);
+        transcript.append_u64(b"token_epochs", token_epochs.0);
+        transcript.append_u64(b"range_proof_bits", range_proof_bits as u64);
+        let mut parameters_hash = [0u8; 32];
+        transcript.challenge_bytes(b"hash", &mut parameters_hash);
+        DeploymentId {
+            name: name.to_owned(),
+            wallet_epochs,
+            token_epochs,
+            range_proof_bits,
+            parameters_hash,
+            accepted_versions: Some(PROTOCOL_VERSION).into_iter().collect(),
+        }
+    }
+
+    /// The name of the deployment.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The hash of the deployment's parameter set.
+    pub fn parameters_hash(&self) -> &[u8; 32] {
+        &self.parameters_hash
+    }
+
+    /// Accept requests using any of `versions`, instead of only
+    /// [`PROTOCOL_VERSION`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `versions` is empty.
+    pub fn with_accepted_versions(mut self, versions: impl IntoIterator<Item = u16>) -> Self {
+        self.accepted_versions = versions.into_iter().collect();
+        assert!(
+            !self.accepted_versions.is_empty(),
+            "no accepted protocol versions"
+        );
+        self
+    }
+
+    /// The protocol versions the deployment's issuer accepts.
+    pub fn accepted_versions(&self) -> impl Iterator<Item = u16> + '_ {
+        self.accepted_versions.iter().copied()
+    }
+
+    /// Check that a request's protocol `version` is accepted, failing with
+    /// [`Error::UpgradeRequired`] if it is older than every accepted version.
+    pub(crate) fn check_version(&self, version: u16) -> Result<(), Error> {
+        if self.accepted_versions.contains(&version) {
+            return Ok(());
+        }
+        match self.accepted_versions.iter().next() {
+            Some(&oldest) if version < oldest => Err(Error::UpgradeRequired(version)),
+            _ => Err(Error::ProtocolVersion(version)),
+        }
+    }
+
+    /// Check that wallet issuer parameters belong to this deployment's
+    /// parameter set, failing with [`Error::DeploymentMismatch`] otherwise.
+    pub(crate) fn check_wallet_parameters(&self, params: &wallet::Parameters) -> Result<(), Error> {
+        if params.epoch.params != self.wallet_epochs {
+            return Err(Error::DeploymentMismatch);
+        }
+        Ok(())
+    }
+
+    /// Check that token issuer parameters belong to this deployment's
+    /// parameter set, failing with [`Error::DeploymentMismatch`] otherwise.
+    pub(crate) fn check_token_parameters(&self, params: &token::Parameters) -> Result<(), Error> {
+        if params.epoch.params != self.token_epochs
+            || params.range_proof_bits != self.range_proof_bits
+        {
+            return Err(Error::DeploymentMismatch);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,67 @@
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::Error;
+
+/// The version of the wire format, sent as the first byte of every encoded
+/// message.
+pub(crate) const WIRE_FORMAT_VERSION: u8 = 1;
+
+/// The maximum length of an encoded message body.
+///
+/// The largest messages are a few kilobytes, so this bounds the allocations
+/// made while decoding a malformed message.
+const MAX_MESSAGE_LEN: u64 = 16 * 1024;
+
+/// Encode `message` as the wire format version followed by its bincode
+/// encoding.
+pub(crate) fn to_bytes<T: Serialize>(message: &T) -> Vec<u8> {
+    let mut bytes = vec![WIRE_FORMAT_VERSION];
+    bincode::serialize_into(&mut bytes, message).expect("messages are always encodable");
+    bytes
+}
+
+/// Decode a message encoded with [`to_bytes`], checking its version and that
+/// its body is exactly one message no longer than the length limit.
+pub(crate) fn from_bytes<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, Error> {
+    let (&version, mut body) = bytes.split_first().ok_or(Error::Encoding)?;
+    if version != WIRE_FORMAT_VERSION {
+        return Err(Error::WireFormatVersion(version));
+    }
+    if body.len() as u64 > MAX_MESSAGE_LEN {
+        return Err(Error::Encoding);
+    }
+    let message = bincode::config()
+        .limit(MAX_MESSAGE_LEN)
+        .deserialize_from(&mut body)
+        .map_err(|_| Error::Encoding)?;
+    if !body.is_empty() {
+        return Err(Error::Encoding);
+    }
+    Ok(message)
+}
+
+/// Add `to_bytes` and `from_bytes` methods using the wire format to a
+/// protocol message type.
+macro_rules! impl_wire_format {
+    ($message:ident) => {
+        impl_wire_format!(@impl [] $message);
+    };
+    ($message:ident<$param:ident: $bound:path>) => {
+        impl_wire_format!(@impl [$param: $bound] $message<$param>);
+    };
+    (@impl [$($generics:tt)*] $message:ty) => {
+        impl<$($generics)*> $message {
+            /// Encode this message in the versioned wire format.
+            pub fn to_bytes(&self) -> Vec<u8> {
+                crate::encoding::to_bytes(self)
+            }
+
+            /// Decode a message in the versioned wire format, rejecting
+            /// unknown versions and malformed, truncated or overlong
+            /// encodings.
+            pub fn from_bytes(bytes: &[u8]) -> Result<Self, crate::Error> {
+                crate::encoding::from_bytes(bytes)
+            }
+        }
+    };
+}
@@ -1,10 +1,20 @@
+use std::convert::TryFrom;
+
 use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::Error;
 
-#[derive(Copy, Clone, Eq, PartialEq, Debug)]
-pub struct EpochParameters(u64);
+/// The duration of each epoch, in seconds.
+///
+/// Decoding rejects a zero duration, which would divide by zero in the
+/// epoch arithmetic.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+#[serde(try_from = "u64")]
+pub struct EpochParameters(pub(crate) u64);
 
 // XXX should this have a phantom type parameter instead of just bundling the params?
-#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
 pub struct Epoch {
     pub(crate) index: i64,
     pub(crate) params: EpochParameters,
@@ -23,7 +33,25 @@ impl From<std::time::Duration> for EpochParameters {
     }
 }
 
+impl TryFrom<u64> for EpochParameters {
+    type Error = Error;
+
+    fn try_from(duration: u64) -> Result<Self, Error> {
+        let params = EpochParameters(duration);
+        if !params.is_well_formed() {
+            return Err(Error::Encoding);
+        }
+        Ok(params)
+    }
+}
+
 impl EpochParameters {
+    /// Returns `true` if the epoch duration is nonzero and fits the epoch
+    /// arithmetic, as it does for every duration of at least one second.
+    pub(crate) fn is_well_formed(&self) -> bool {
+        self.0 > 0 && self.0 <= i64::MAX as u64
+    }
+
     pub fn epoch_at(&self, time: DateTime<Utc>) -> Epoch {
         Epoch {
             index: time.timestamp() / (self.0 as i64),
@@ -35,7 +63,7 @@ impl EpochParameters {
 impl Epoch {
     pub fn state_at(&self, time: DateTime<Utc>) -> EpochState {
         let current = self.params.epoch_at(time);
-        match current.index - self.index {
+        match current.index.saturating_sub(self.index) {
             -1 => EpochState::Active,
             0 => EpochState::Primary,
             1 => EpochState::Active,
@@ -43,4 +71,24 @@ impl Epoch {
             _ => EpochState::Invalid,
         }
     }
+
+    /// Returns `true` if the epoch duration is nonzero and fits the epoch
+    /// arithmetic, as it does for every epoch built from a `Duration`.
+    pub(crate) fn is_well_formed(&self) -> bool {
+        self.params.is_well_formed()
+    }
+
+    /// Check that a credential from this epoch can be presented at `time`.
+    ///
+    /// Credentials can be presented while their epoch is Active or Primary,
+    /// so that clients whose clocks are slightly off are not forced to roll
+    /// over early, while a credential in the Rollover state can only be
+    /// rolled over.
+    pub(crate) fn check_presentable(&self, time: DateTime<Utc>) -> Result<(), Error> {
+        match self.state_at(time) {
+            EpochState::Active | EpochState::Primary => Ok(()),
+            EpochState::Rollover => Err(Error::RolloverRequired),
+            EpochState::Invalid => Err(Error::InactiveEpoch),
+        }
+    }
 }
@@ -0,0 +1,144 @@
+use std::{fmt, io};
+
+use crate::CredentialType;
+
+/// An error in one of the protocol steps.
+#[derive(Debug)]
+pub enum Error {
+    /// A message contained a point which is not a valid Ristretto point.
+    Decompression,
+    /// A message is for a different epoch than the keys processing it.
+    WrongEpoch,
+    /// The epoch of a credential being rolled over is not in the Active,
+    /// Primary or Rollover state.
+    OldEpochState,
+    /// The epoch being rolled over to is not in the Active or Primary state.
+    NewEpochState,
+    /// The presented credential's epoch is in the Rollover state, so it must
+    /// be rolled over before it can be used.
+    RolloverRequired,
+    /// The presented credential's epoch is not in the Active or Primary
+    /// state.
+    InactiveEpoch,
+    /// The client's proof failed to verify.
+    ClientProof,
+    /// The issuer's proof failed to verify.
+    IssuerProof,
+    /// A range proof could not be created or failed to verify.
+    RangeProof,
+    /// The revealed nullifier has already been spent.
+    NullifierReuse(CredentialType),
+    /// The nullifier store failed to check or record a nullifier.
+    NullifierStore(io::Error),
+    /// A token value does not fit in the token parameters' range proof size.
+    ValueOutOfRange,
+    /// A credential's balance is smaller than the amount being removed.
+    InsufficientBalance,
+    /// A message was not a valid encoding in the wire format.
+    Encoding,
+    /// A message was encoded with an unsupported wire format version.
+    WireFormatVersion(u8),
+    /// A request uses a protocol version older than any the issuer accepts,
+    /// so the client must be upgraded.
+    UpgradeRequired(u16),
+    /// A message uses a protocol version which is not accepted.
+    ProtocolVersion(u16),
+    /// A sealed container has an unsupported format version.
+    ContainerVersion(u8),
+    /// A sealed container failed to decrypt, because the key is wrong or the
+    /// container was modified.
+    Decryption,
+    /// A stored credential has a malformed epoch or tag.
+    MalformedCredential,
+    /// Parameters for an epoch conflict with the parameters already known
+    /// for that epoch.
+    ConflictingParameters,
+    /// No parameters are known for an epoch.
+    MissingParameters,
+    /// A key file has an unsupported format version.
+    KeyFileVersion(u8),
+    /// A key file holds secrets for the given credential type, rather than
+    /// the one being imported.
+    WrongCredentialType(CredentialType),
+    /// The public parameters stored in a key file do not match its secrets.
+    ParametersMismatch,
+    /// The issuer's parameters do not match the epoch durations or token
+    /// rangeproof size of its deployment.
+    DeploymentMismatch,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Decompression => write!(f, "bad point"),
+            Error::WrongEpoch => write!(f, "wrong epoch"),
+            Error::OldEpochState => {
+                write!(f, "old epoch not in Active, Primary, or Rollover state")
+            }
+            Error::NewEpochState => write!(f, "new epoch not in Active or Primary state"),
+            Error::RolloverRequired => write!(f, "credential must be rolled over"),
+            Error::InactiveEpoch => write!(f, "credential epoch not in Active or Primary state"),
+            Error::ClientProof => write!(f, "client proof failed to verify"),
+            Error::IssuerProof => write!(f, "issuer proof failed to verify"),
+            Error::RangeProof => write!(f, "range proof failed"),
+            Error::NullifierReuse(CredentialType::Wallet) => {
+                write!(f, "nullifier is in wallet nullifier set")
+            }
+            Error::NullifierReuse(CredentialType::Token) => {
+                write!(f, "nullifier is in token nullifier set")
+            }
+            Error::NullifierStore(e) => write!(f, "nullifier store failed: {}", e),
+            Error::ValueOutOfRange => write!(f, "token value out of range"),
+            Error::InsufficientBalance => write!(f, "insufficient balance"),
+            Error::Encoding => write!(f, "malformed message encoding"),
+            Error::WireFormatVersion(version) => {
+                write!(f, "unsupported wire format version {}", version)
+            }
+            Error::UpgradeRequired(version) => write!(
+                f,
+                "protocol version {} is no longer accepted, upgrade required",
+                version
+            ),
+            Error::ProtocolVersion(version) => {
+                write!(f, "unsupported protocol version {}", version)
+            }
+            Error::ContainerVersion(version) => {
+                write!(f, "unsupported container version {}", version)
+            }
+            Error::Decryption => write!(f, "sealed container failed to decrypt"),
+            Error::MalformedCredential => write!(f, "stored credential is malformed"),
+            Error::ConflictingParameters => write!(f, "conflicting parameters for epoch"),
+            Error::MissingParameters => write!(f, "no parameters for epoch"),
+            Error::KeyFileVersion(version) => {
+                write!(f, "unsupported key file version {}", version)
+            }
+            Error::WrongCredentialType(CredentialType::Wallet) => {
+                write!(f, "key file holds wallet secrets")
+            }
+            Error::WrongCredentialType(CredentialType::Token) => {
+                write!(f, "key file holds token secrets")
+            }
+            Error::ParametersMismatch => {
+                write!(f, "stored parameters do not match stored secrets")
+            }
+            Error::DeploymentMismatch => {
+                write!(f, "issuer parameters do not match the deployment")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::NullifierStore(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::NullifierStore(e)
+    }
+}
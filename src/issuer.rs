@@ -0,0 +1,89 @@
+use std::fmt;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{Clock, CredentialType, DeploymentId, Epoch, Error, NullifierStore, ResponseCache};
+
+/// The issuer-side state shared by every issuer entry point: the deployment
+/// it serves, the [`Clock`] used to determine epoch states, the
+/// [`NullifierStore`] holding each epoch's nullifier set, and optionally a
+/// [`ResponseCache`].
+///
+/// An issuer builds one context and passes it to each request it
+/// processes, so that every endpoint is checked against the same
+/// deployment, time source and nullifier sets.
+pub struct IssuerContext<'a, C, N> {
+    pub(crate) deployment: &'a DeploymentId,
+    pub(crate) clock: &'a C,
+    pub(crate) nullifiers: &'a mut N,
+    cache: Option<&'a mut ResponseCache>,
+}
+
+impl<'a, C, N> IssuerContext<'a, C, N> {
+    /// Create a context for the issuer of `deployment`, reading the time
+    /// from `clock` and recording nullifiers in `nullifiers`.
+    pub fn new(deployment: &'a DeploymentId, clock: &'a C, nullifiers: &'a mut N) -> Self {
+        IssuerContext {
+            deployment,
+            clock,
+            nullifiers,
+            cache: None,
+        }
+    }
+
+    /// Answer retried requests from `cache`.
+    ///
+    /// Every entry point which presents a credential uses the cache: wallet
+    /// topups, debits and rollovers, and token purchases, spends and
+    /// rollovers.  Wallet issuance reveals no nullifier, so it is never
+    /// cached.
+    pub fn with_cache(mut self, cache: &'a mut ResponseCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// The deployment this issuer serves.
+    pub fn deployment(&self) -> &DeploymentId {
+        self.deployment
+    }
+}
+
+impl<'a, C: Clock, N: NullifierStore> IssuerContext<'a, C, N> {
+    /// Return the cached response to the encoded request `request` of the
+    /// protocol `label`, which reveals `nullifier` from `epoch`, or compute
+    /// it with `respond`.
+    ///
+    /// Without a cache, the response is always computed.
+    pub(crate) fn respond<T, F>(
+        &mut self,
+        label: &'static [u8],
+        credential: CredentialType,
+        epoch: Epoch,
+        nullifier: [u8; 32],
+        request: &[u8],
+        respond: F,
+    ) -> Result<T, Error>
+    where
+        T: Serialize + DeserializeOwned,
+        F: FnOnce(&mut Self) -> Result<T, Error>,
+    {
+        let cache = match self.cache.take() {
+            Some(cache) => cache,
+            None => return respond(self),
+        };
+        cache.prune(self.clock.now());
+        let response = cache.get_or_respond(label, credential, epoch, nullifier, request, || {
+            respond(self)
+        });
+        self.cache = Some(cache);
+        response
+    }
+}
+
+impl<'a, C, N> fmt::Debug for IssuerContext<'a, C, N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("IssuerContext")
+            .field("deployment", self.deployment)
+            .finish()
+    }
+}
@@ -0,0 +1,261 @@
+use std::convert::TryInto;
+use std::fmt;
+
+use curve25519_dalek::scalar::Scalar;
+use hmac::Hmac;
+use rand_core::{CryptoRng, RngCore};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::Sha512;
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::{container, CredentialType, Epoch, Error, IssuerSecrets};
+
+/// The version of the key file format, sent as its first byte.
+const KEY_FILE_VERSION: u8 = 1;
+
+/// The key file is encrypted under a key provided by the caller.
+const KDF_NONE: u8 = 0;
+/// The key file is encrypted under a key derived from a passphrase with
+/// PBKDF2-HMAC-SHA512.
+const KDF_PBKDF2: u8 = 1;
+
+const SALT_LEN: usize = 16;
+
+/// The PBKDF2 iteration count for new passphrase-protected key files.
+const PBKDF2_ITERATIONS: u32 = 100_000;
+
+/// The largest PBKDF2 iteration count accepted when importing, which bounds
+/// the work done for a malformed key file.
+const MAX_PBKDF2_ITERATIONS: u32 = 10_000_000;
+
+/// The container label for the secrets of a single epoch.
+const SECRETS_LABEL: &[u8] = b"danake key file: issuer secrets";
+
+/// The key protecting an exported key file.
+#[derive(Copy, Clone)]
+pub enum KeyFileKey<'a> {
+    /// A key provided by the caller, such as one held by a key management
+    /// service.
+    Key(&'a [u8; 32]),
+    /// A passphrase, from which the key is derived with PBKDF2-HMAC-SHA512
+    /// and a random salt stored in the key file.
+    Passphrase(&'a str),
+}
+
+impl<'a> fmt::Debug for KeyFileKey<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeyFileKey::Key(_) => write!(f, "Key(..)"),
+            KeyFileKey::Passphrase(_) => write!(f, "Passphrase(..)"),
+        }
+    }
+}
+
+/// Issuer secrets for one epoch as stored in a key file, together with the
+/// public parameters they had when exported.
+///
+/// The credential type comes first, so that [`read`] can check it before
+/// decoding the rest.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct StoredSecrets<C, P> {
+    credential: CredentialType,
+    epoch: Epoch,
+    config: C,
+    scalars: [Scalar; 4],
+    cached_params: P,
+}
+
+impl<C, P> Drop for StoredSecrets<C, P> {
+    fn drop(&mut self) {
+        for scalar in self.scalars.iter_mut() {
+            scalar.zeroize();
+        }
+    }
+}
+
+/// Prepare `secrets` for storage in a key file.
+pub(crate) fn store<S: IssuerSecrets>(secrets: &S) -> StoredSecrets<S::Config, S::Parameters> {
+    StoredSecrets {
+        credential: S::CREDENTIAL,
+        epoch: secrets.epoch(),
+        config: secrets.config(),
+        scalars: secrets.scalars(),
+        cached_params: secrets.parameters(),
+    }
+}
+
+/// Rebuild secrets stored in a key file, checking that they are for the
+/// right credential type and that their public parameters match the stored
+/// ones.
+pub(crate) fn restore<S: IssuerSecrets>(
+    stored: &StoredSecrets<S::Config, S::Parameters>,
+) -> Result<S, Error> {
+    if stored.credential != S::CREDENTIAL {
+        return Err(Error::WrongCredentialType(stored.credential));
+    }
+    if !stored.epoch.is_well_formed() || !S::is_valid_config(stored.config) {
+        return Err(Error::Encoding);
+    }
+    let secrets = S::from_scalars(stored.config, stored.epoch, stored.scalars);
+    if secrets.parameters() != stored.cached_params {
+        return Err(Error::ParametersMismatch);
+    }
+    Ok(secrets)
+}
+
+/// Export the secrets for one epoch as a key file.
+pub(crate) fn export_secrets<S: IssuerSecrets, R: RngCore + CryptoRng>(
+    secrets: &S,
+    key: KeyFileKey,
+    rng: R,
+) -> Vec<u8> {
+    write(SECRETS_LABEL, &store(secrets), key, rng)
+}
+
+/// Import the secrets for one epoch from a key file.
+pub(crate) fn import_secrets<S: IssuerSecrets>(file: &[u8], key: KeyFileKey) -> Result<S, Error> {
+    let stored: StoredSecrets<S::Config, S::Parameters> =
+        read(SECRETS_LABEL, S::CREDENTIAL, file, key)?;
+    restore(&stored)
+}
+
+/// Encrypt `contents` into a key file under `key`.
+///
+/// The key file is the format version, the key derivation method and its
+/// settings, and a sealed container bound to `label`.
+pub(crate) fn write<T: Serialize, R: RngCore + CryptoRng>(
+    label: &'static [u8],
+    contents: &T,
+    key: KeyFileKey,
+    mut rng: R,
+) -> Vec<u8> {
+    let plaintext = Zeroizing::new(
+        bincode::serialize(contents).expect("key file contents can always be serialized"),
+    );
+
+    let mut file = vec![KEY_FILE_VERSION];
+    let key = match key {
+        KeyFileKey::Key(key) => {
+            file.push(KDF_NONE);
+            Zeroizing::new(*key)
+        }
+        KeyFileKey::Passphrase(passphrase) => {
+            let mut salt = [0u8; SALT_LEN];
+            rng.fill_bytes(&mut salt);
+            file.push(KDF_PBKDF2);
+            file.extend_from_slice(&salt);
+            file.extend_from_slice(&PBKDF2_ITERATIONS.to_le_bytes());
+            Zeroizing::new(derive_key(passphrase.as_bytes(), &salt, PBKDF2_ITERATIONS))
+        }
+    };
+    file.extend_from_slice(&container::seal(label, &plaintext, &key, rng));
+    file
+}
+
+/// Decrypt the contents of a key file written by [`write`] with the same
+/// `label`, whose contents must start with `credential`.
+///
+/// A key of the wrong kind for the file fails like a wrong key, with
+/// [`Error::Decryption`].
+pub(crate) fn read<T: DeserializeOwned>(
+    label: &'static [u8],
+    credential: CredentialType,
+    file: &[u8],
+    key: KeyFileKey,
+) -> Result<T, Error> {
+    let (&version, rest) = file.split_first().ok_or(Error::Encoding)?;
+    if version != KEY_FILE_VERSION {
+        return Err(Error::KeyFileVersion(version));
+    }
+    let (&kdf, rest) = rest.split_first().ok_or(Error::Encoding)?;
+    let (key, sealed) = match (kdf, key) {
+        (KDF_NONE, KeyFileKey::Key(key)) => (Zeroizing::new(*key), rest),
+        (KDF_PBKDF2, KeyFileKey::Passphrase(passphrase)) => {
+            if rest.len() < SALT_LEN + 4 {
+                return Err(Error::Encoding);
+            }
+            let (salt, rest) = rest.split_at(SALT_LEN);
+            let (iterations, sealed) = rest.split_at(4);
+            let iterations = u32::from_le_bytes(iterations.try_into().unwrap());
+            if iterations == 0 || iterations > MAX_PBKDF2_ITERATIONS {
+                return Err(Error::Encoding);
+            }
+            let key = derive_key(passphrase.as_bytes(), salt, iterations);
+            (Zeroizing::new(key), sealed)
+        }
+        (KDF_NONE, _) | (KDF_PBKDF2, _) => return Err(Error::Decryption),
+        _ => return Err(Error::Encoding),
+    };
+    let plaintext = Zeroizing::new(container::open(label, sealed, &key)?);
+    let stored: CredentialType = bincode::deserialize(&plaintext).map_err(|_| Error::Encoding)?;
+    if stored != credential {
+        return Err(Error::WrongCredentialType(stored));
+    }
+    bincode::deserialize(&plaintext).map_err(|_| Error::Encoding)
+}
+
+/// Derive a 32-byte key from `passphrase` with PBKDF2-HMAC-SHA512, as in
+/// RFC 8018.
+fn derive_key(passphrase: &[u8], salt: &[u8], iterations: u32) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    pbkdf2::pbkdf2::<Hmac<Sha512>>(passphrase, salt, iterations as usize, &mut key);
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::derive_key;
+
+    /// Published PBKDF2-HMAC-SHA512 test vectors, in the style of RFC 6070,
+    /// truncated to the 32-byte key length used for key files, checking that
+    /// the key derivation is wired to HMAC-SHA512.
+    #[test]
+    fn pbkdf2_matches_known_answers() {
+        let vectors: [(&[u8], &[u8], u32, [u8; 32]); 4] = [
+            (
+                &b"password"[..],
+                &b"salt"[..],
+                1,
+                [
+                    0x86, 0x7f, 0x70, 0xcf, 0x1a, 0xde, 0x02, 0xcf, 0xf3, 0x75, 0x25, 0x99, 0xa3,
+                    0xa5, 0x3d, 0xc4, 0xaf, 0x34, 0xc7, 0xa6, 0x69, 0x81, 0x5a, 0xe5, 0xd5, 0x13,
+                    0x55, 0x4e, 0x1c, 0x8c, 0xf2, 0x52,
+                ],
+            ),
+            (
+                &b"password"[..],
+                &b"salt"[..],
+                2,
+                [
+                    0xe1, 0xd9, 0xc1, 0x6a, 0xa6, 0x81, 0x70, 0x8a, 0x45, 0xf5, 0xc7, 0xc4, 0xe2,
+                    0x15, 0xce, 0xb6, 0x6e, 0x01, 0x1a, 0x2e, 0x9f, 0x00, 0x40, 0x71, 0x3f, 0x18,
+                    0xae, 0xfd, 0xb8, 0x66, 0xd5, 0x3c,
+                ],
+            ),
+            (
+                &b"password"[..],
+                &b"salt"[..],
+                4096,
+                [
+                    0xd1, 0x97, 0xb1, 0xb3, 0x3d, 0xb0, 0x14, 0x3e, 0x01, 0x8b, 0x12, 0xf3, 0xd1,
+                    0xd1, 0x47, 0x9e, 0x6c, 0xde, 0xbd, 0xcc, 0x97, 0xc5, 0xc0, 0xf8, 0x7f, 0x69,
+                    0x02, 0xe0, 0x72, 0xf4, 0x57, 0xb5,
+                ],
+            ),
+            (
+                &b"passwordPASSWORDpassword"[..],
+                &b"saltSALTsaltSALTsaltSALTsaltSALTsalt"[..],
+                4096,
+                [
+                    0x8c, 0x05, 0x11, 0xf4, 0xc6, 0xe5, 0x97, 0xc6, 0xac, 0x63, 0x15, 0xd8, 0xf0,
+                    0x36, 0x2e, 0x22, 0x5f, 0x3c, 0x50, 0x14, 0x95, 0xba, 0x23, 0xb8, 0x68, 0xc0,
+                    0x05, 0x17, 0x4d, 0xc4, 0xee, 0x71,
+                ],
+            ),
+        ];
+        for (passphrase, salt, iterations, key) in vectors.iter() {
+            assert_eq!(&derive_key(passphrase, salt, *iterations), key);
+        }
+    }
+}
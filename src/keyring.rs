@@ -0,0 +1,360 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use curve25519_dalek::scalar::Scalar;
+use rand_core::{CryptoRng, RngCore};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroize;
+
+use crate::keyfile::{self, KeyFileKey, StoredSecrets};
+use crate::{
+    token, wallet, CredentialType, Epoch, EpochParameters, EpochState, Error, MasterSeed,
+    RolloverPolicy,
+};
+
+/// The container label for a whole key ring.
+const KEY_RING_LABEL: &[u8] = b"danake key file: key ring";
+
+/// Issuer secrets which can be held in a [`KeyRing`].
+pub trait IssuerSecrets: Sized {
+    /// The public parameters for these secrets.
+    type Parameters: PartialEq + Serialize + DeserializeOwned;
+    /// The settings shared by the secrets for every epoch.
+    #[doc(hidden)]
+    type Config: Copy + PartialEq + std::fmt::Debug + Serialize + DeserializeOwned;
+    /// The credential type these secrets issue.
+    #[doc(hidden)]
+    const CREDENTIAL: CredentialType;
+
+    /// The epoch these secrets are for.
+    fn epoch(&self) -> Epoch;
+
+    /// The public parameters for these secrets.
+    fn parameters(&self) -> Self::Parameters;
+
+    #[doc(hidden)]
+    fn generate<R: RngCore + CryptoRng>(config: Self::Config, epoch: Epoch, rng: R) -> Self;
+
+    #[doc(hidden)]
+    fn derive(config: Self::Config, seed: &MasterSeed, epoch: Epoch) -> Self;
+
+    #[doc(hidden)]
+    fn config(&self) -> Self::Config;
+
+    #[doc(hidden)]
+    fn is_valid_config(config: Self::Config) -> bool;
+
+    #[doc(hidden)]
+    fn scalars(&self) -> [Scalar; 4];
+
+    #[doc(hidden)]
+    fn from_scalars(config: Self::Config, epoch: Epoch, scalars: [Scalar; 4]) -> Self;
+}
+
+impl IssuerSecrets for wallet::Secrets {
+    type Parameters = wallet::Parameters;
+    type Config = ();
+    const CREDENTIAL: CredentialType = CredentialType::Wallet;
+
+    fn epoch(&self) -> Epoch {
+        self.inner.epoch
+    }
+
+    fn parameters(&self) -> wallet::Parameters {
+        self.cached_params
+    }
+
+    fn generate<R: RngCore + CryptoRng>(_: (), epoch: Epoch, rng: R) -> Self {
+        wallet::Secrets::new(epoch, rng)
+    }
+
+    fn derive(_: (), seed: &MasterSeed, epoch: Epoch) -> Self {
+        wallet::Secrets::derive(seed, epoch)
+    }
+
+    fn config(&self) {}
+
+    fn is_valid_config(_: ()) -> bool {
+        true
+    }
+
+    fn scalars(&self) -> [Scalar; 4] {
+        wallet::Secrets::scalars(self)
+    }
+
+    fn from_scalars(_: (), epoch: Epoch, scalars: [Scalar; 4]) -> Self {
+        wallet::Secrets::from_scalars(epoch, scalars)
+    }
+}
+
+impl IssuerSecrets for token::Secrets {
+    type Parameters = token::Parameters;
+    type Config = usize;
+    const CREDENTIAL: CredentialType = CredentialType::Token;
+
+    fn epoch(&self) -> Epoch {
+        self.inner.epoch
+    }
+
+    fn parameters(&self) -> token::Parameters {
+        self.cached_params
+    }
+
+    fn generate<R: RngCore + CryptoRng>(range_proof_bits: usize, epoch: Epoch, rng: R) -> Self {
+        token::Secrets::new(epoch, range_proof_bits, rng)
+    }
+
+    fn derive(range_proof_bits: usize, seed: &MasterSeed, epoch: Epoch) -> Self {
+        token::Secrets::derive(seed, epoch, range_proof_bits)
+    }
+
+    fn config(&self) -> usize {
+        self.inner.range_proof_bits
+    }
+
+    fn is_valid_config(range_proof_bits: usize) -> bool {
+        [8, 16, 32, 64].contains(&range_proof_bits)
+    }
+
+    fn scalars(&self) -> [Scalar; 4] {
+        token::Secrets::scalars(self)
+    }
+
+    fn from_scalars(range_proof_bits: usize, epoch: Epoch, scalars: [Scalar; 4]) -> Self {
+        token::Secrets::from_scalars(epoch, range_proof_bits, scalars)
+    }
+}
+
+/// An issuer's secrets for one credential type, managed according to the
+/// epoch key schedule.
+///
+/// The secrets for each epoch are generated one epoch ahead of time, so that
+/// their parameters can be published while they are Active and before they
+/// become Primary.  Secrets are kept while their epoch is in the Active,
+/// Primary, or Rollover state, or for longer if the key ring's
+/// [`RolloverPolicy`] accepts older rollovers, and overwritten and dropped
+/// once it expires.
+///
+/// A key ring created from a [`MasterSeed`] derives each epoch's secrets
+/// from the seed, so that a replacement issuer with the same seed holds the
+/// same secrets.  Otherwise, the secrets are generated randomly.
+///
+/// The key ring does not track time itself: call [`KeyRing::update`]
+/// regularly, at least once per epoch, with the current time.
+#[derive(Debug)]
+pub struct KeyRing<S: IssuerSecrets> {
+    epoch_params: EpochParameters,
+    config: S::Config,
+    seed: Option<MasterSeed>,
+    policy: RolloverPolicy,
+    secrets: BTreeMap<i64, S>,
+}
+
+/// A key ring as stored in a key file, starting with its credential type
+/// like [`StoredSecrets`].
+#[derive(Serialize, Deserialize)]
+struct StoredKeyRing<C, P> {
+    credential: CredentialType,
+    epoch_params: EpochParameters,
+    config: C,
+    seed: Option<[u8; 32]>,
+    max_rollover_epochs: u32,
+    secrets: Vec<StoredSecrets<C, P>>,
+}
+
+impl<C, P> Drop for StoredKeyRing<C, P> {
+    fn drop(&mut self) {
+        if let Some(seed) = &mut self.seed {
+            seed.zeroize();
+        }
+    }
+}
+
+impl KeyRing<wallet::Secrets> {
+    /// Create an empty key ring for wallet secrets with epochs of the given
+    /// duration.
+    pub fn new(epoch_params: EpochParameters) -> Self {
+        KeyRing::with_config(epoch_params, (), None)
+    }
+
+    /// Create an empty key ring for wallet secrets with epochs of the given
+    /// duration, deriving the secrets from `seed`.
+    pub fn from_seed(epoch_params: EpochParameters, seed: MasterSeed) -> Self {
+        KeyRing::with_config(epoch_params, (), Some(seed))
+    }
+}
+
+impl KeyRing<token::Secrets> {
+    /// Create an empty key ring for token secrets with epochs of the given
+    /// duration and the given range proof size.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `range_proof_bits` is not one of 8, 16, 32, or 64.
+    pub fn new(epoch_params: EpochParameters, range_proof_bits: usize) -> Self {
+        assert!(
+            [8, 16, 32, 64].contains(&range_proof_bits),
+            "unsupported rangeproof bit size"
+        );
+        KeyRing::with_config(epoch_params, range_proof_bits, None)
+    }
+
+    /// Create an empty key ring for token secrets with epochs of the given
+    /// duration and the given range proof size, deriving the secrets from
+    /// `seed`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `range_proof_bits` is not one of 8, 16, 32, or 64.
+    pub fn from_seed(
+        epoch_params: EpochParameters,
+        range_proof_bits: usize,
+        seed: MasterSeed,
+    ) -> Self {
+        assert!(
+            [8, 16, 32, 64].contains(&range_proof_bits),
+            "unsupported rangeproof bit size"
+        );
+        KeyRing::with_config(epoch_params, range_proof_bits, Some(seed))
+    }
+}
+
+impl<S: IssuerSecrets> KeyRing<S> {
+    fn with_config(
+        epoch_params: EpochParameters,
+        config: S::Config,
+        seed: Option<MasterSeed>,
+    ) -> Self {
+        KeyRing {
+            epoch_params,
+            config,
+            seed,
+            policy: RolloverPolicy::default(),
+            secrets: BTreeMap::new(),
+        }
+    }
+
+    /// Keep secrets for as long as `policy` accepts rollovers.
+    pub fn with_rollover_policy(mut self, policy: RolloverPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// The epoch duration of this key ring.
+    pub fn epoch_params(&self) -> EpochParameters {
+        self.epoch_params
+    }
+
+    /// Generate the secrets for the current and next epochs at `time` if they
+    /// do not exist yet, and drop the secrets of every expired epoch.
+    pub fn update<R: RngCore + CryptoRng>(&mut self, time: DateTime<Utc>, mut rng: R) {
+        let current = self.epoch_params.epoch_at(time);
+
+        let expired: Vec<i64> = self
+            .secrets
+            .values()
+            .map(|secrets| secrets.epoch())
+            .filter(|epoch| self.policy.has_expired_at(epoch, time))
+            .map(|epoch| epoch.index)
+            .collect();
+        for index in expired {
+            self.secrets.remove(&index);
+        }
+
+        for index in current.index..=current.index + 1 {
+            let epoch = Epoch {
+                index,
+                params: self.epoch_params,
+            };
+            let (config, seed) = (self.config, &self.seed);
+            self.secrets.entry(index).or_insert_with(|| match seed {
+                Some(seed) => S::derive(config, seed, epoch),
+                None => S::generate(config, epoch, &mut rng),
+            });
+        }
+    }
+
+    /// The secrets for `epoch`, if they are held.
+    pub fn get(&self, epoch: Epoch) -> Option<&S> {
+        if epoch.params != self.epoch_params {
+            return None;
+        }
+        self.secrets.get(&epoch.index)
+    }
+
+    /// The Primary secrets at `time`, used for new issuance.
+    pub fn primary(&self, time: DateTime<Utc>) -> Option<&S> {
+        self.get(self.epoch_params.epoch_at(time))
+    }
+
+    /// The secrets which verify a credential from `epoch` at `time`, if its
+    /// epoch is in the Active, Primary, or Rollover state, or is old enough
+    /// to be rolled over under the key ring's policy.
+    ///
+    /// Credentials in the Rollover state or older can only be rolled over,
+    /// which the protocols check themselves.
+    pub fn verifying(&self, epoch: Epoch, time: DateTime<Utc>) -> Option<&S> {
+        // The epoch comes from the client, so it is checked against the key
+        // ring's schedule before its state is computed with its duration.
+        if epoch.params != self.epoch_params || !epoch.is_well_formed() {
+            return None;
+        }
+        match epoch.state_at(time) {
+            EpochState::Invalid if !self.policy.accepts_at(&epoch, time) => None,
+            _ => self.get(epoch),
+        }
+    }
+
+    /// The public parameters of every epoch held, in epoch order, to be
+    /// published to clients.
+    pub fn parameters(&self) -> Vec<S::Parameters> {
+        self.secrets.values().map(|s| s.parameters()).collect()
+    }
+
+    /// Export the whole key ring, including its seed and rollover policy,
+    /// as a key file encrypted under `key`.
+    pub fn to_key_file<R: RngCore + CryptoRng>(&self, key: KeyFileKey, rng: R) -> Vec<u8> {
+        let stored = StoredKeyRing {
+            credential: S::CREDENTIAL,
+            epoch_params: self.epoch_params,
+            config: self.config,
+            seed: self.seed.as_ref().map(|seed| *seed.as_bytes()),
+            max_rollover_epochs: self.policy.max_epochs(),
+            secrets: self.secrets.values().map(keyfile::store).collect(),
+        };
+        keyfile::write(KEY_RING_LABEL, &stored, key, rng)
+    }
+
+    /// Import a key ring from a key file written by [`KeyRing::to_key_file`].
+    ///
+    /// Fails with [`Error::ParametersMismatch`] if the public parameters
+    /// stored for any epoch are not those of the stored secrets.
+    pub fn from_key_file(file: &[u8], key: KeyFileKey) -> Result<Self, Error> {
+        let stored: StoredKeyRing<S::Config, S::Parameters> =
+            keyfile::read(KEY_RING_LABEL, S::CREDENTIAL, file, key)?;
+        let well_formed = Epoch {
+            index: 0,
+            params: stored.epoch_params,
+        }
+        .is_well_formed();
+        if !well_formed || !S::is_valid_config(stored.config) || stored.max_rollover_epochs < 2 {
+            return Err(Error::Encoding);
+        }
+
+        let seed = stored.seed.map(MasterSeed::from_bytes);
+        let mut ring = KeyRing::with_config(stored.epoch_params, stored.config, seed)
+            .with_rollover_policy(RolloverPolicy::new(stored.max_rollover_epochs));
+        for stored_secrets in &stored.secrets {
+            let secrets: S = keyfile::restore(stored_secrets)?;
+            if secrets.epoch().params != ring.epoch_params {
+                return Err(Error::WrongEpoch);
+            }
+            if secrets.config() != ring.config {
+                return Err(Error::Encoding);
+            }
+            ring.secrets.insert(secrets.epoch().index, secrets);
+        }
+        Ok(ring)
+    }
+}
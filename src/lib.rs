@@ -1,11 +1,42 @@
 #[macro_use]
 extern crate zkp;
 
+mod cache;
+mod clock;
+mod container;
+mod deployment;
+#[macro_use]
+mod encoding;
 mod epoch;
+mod error;
+mod issuer;
+mod keyfile;
+mod keyring;
+mod policy;
+mod presentation;
+mod rollover;
+mod seed;
 mod tag;
+mod transcript;
+mod version;
 
 pub(crate) mod constants;
 pub(crate) use tag::Tag;
+pub(crate) use transcript::TranscriptProtocol;
 
+pub use cache::ResponseCache;
+pub use clock::{Clock, ManualClock, SystemClock};
+pub use deployment::DeploymentId;
 pub use epoch::*;
+pub use error::Error;
+pub use issuer::IssuerContext;
+pub use keyfile::KeyFileKey;
+pub use keyring::{IssuerSecrets, KeyRing};
+pub use policy::RolloverPolicy;
+pub use seed::MasterSeed;
+pub use version::PROTOCOL_VERSION;
+pub mod nullifier;
+pub(crate) use nullifier::Reservation;
+pub use nullifier::{CredentialType, NullifierStore};
+pub mod token;
 pub mod wallet;
@@ -0,0 +1,233 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{Clock, Epoch, RolloverPolicy, SystemClock};
+
+mod file;
+pub use file::FileNullifierStore;
+
+/// The type of credential a nullifier belongs to.
+///
+/// Each credential type has its own issuer parameters for each epoch, and so
+/// its own nullifier set for each epoch.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub enum CredentialType {
+    Wallet,
+    Token,
+}
+
+/// Storage for the nullifier sets of each credential type and epoch.
+///
+/// Presentations use a two-phase protocol: the issuer
+/// [`reserve`](NullifierStore::reserve)s the revealed nullifier before
+/// verifying the presentation, then either
+/// [`commit`](NullifierStore::commit)s it once the presentation has been
+/// fully verified or [`release`](NullifierStore::release)s it if verification
+/// fails.  This way a malformed request cannot burn the nullifier of a
+/// credential, while a nullifier cannot be used by two requests at once.
+pub trait NullifierStore {
+    /// Reserve `nullifier` in the nullifier set for credentials of type
+    /// `credential` in `epoch`.
+    ///
+    /// Returns `Ok(true)` if the nullifier was unspent and unreserved and is
+    /// now reserved, and `Ok(false)` if it was already spent or reserved.
+    fn reserve(
+        &mut self,
+        credential: CredentialType,
+        epoch: Epoch,
+        nullifier: [u8; 32],
+    ) -> io::Result<bool>;
+
+    /// Mark a reserved nullifier as spent.
+    fn commit(
+        &mut self,
+        credential: CredentialType,
+        epoch: Epoch,
+        nullifier: [u8; 32],
+    ) -> io::Result<()>;
+
+    /// Release a reserved nullifier, leaving it unspent.
+    fn release(&mut self, credential: CredentialType, epoch: Epoch, nullifier: [u8; 32]);
+
+    /// The policy bounding which credentials can be rolled over.
+    ///
+    /// Rollovers are checked against the store's policy, so that a
+    /// credential is only accepted while its nullifier set is retained.
+    fn rollover_policy(&self) -> RolloverPolicy {
+        RolloverPolicy::default()
+    }
+
+    /// Check whether `nullifier` is in the nullifier set for credentials of
+    /// type `credential` in `epoch`, adding it to the set if not.
+    ///
+    /// Returns `Ok(true)` if the nullifier was unspent and has now been
+    /// recorded, and `Ok(false)` if it was already spent.
+    fn check_and_insert(
+        &mut self,
+        credential: CredentialType,
+        epoch: Epoch,
+        nullifier: [u8; 32],
+    ) -> io::Result<bool> {
+        if !self.reserve(credential, epoch, nullifier)? {
+            return Ok(false);
+        }
+        match self.commit(credential, epoch, nullifier) {
+            Ok(()) => Ok(true),
+            Err(e) => {
+                self.release(credential, epoch, nullifier);
+                Err(e)
+            }
+        }
+    }
+}
+
+/// A nullifier reserved while a presentation is verified.
+///
+/// The nullifier is released when the reservation is dropped, unless it was
+/// committed first, so every early return from a failed verification leaves
+/// the nullifier spendable.
+pub(crate) struct Reservation<'a, S: NullifierStore> {
+    store: &'a mut S,
+    credential: CredentialType,
+    epoch: Epoch,
+    nullifier: [u8; 32],
+    committed: bool,
+}
+
+impl<'a, S: NullifierStore> Reservation<'a, S> {
+    /// Reserve `nullifier`, returning `Ok(None)` if it is already spent or
+    /// reserved.
+    pub(crate) fn new(
+        store: &'a mut S,
+        credential: CredentialType,
+        epoch: Epoch,
+        nullifier: [u8; 32],
+    ) -> io::Result<Option<Self>> {
+        if !store.reserve(credential, epoch, nullifier)? {
+            return Ok(None);
+        }
+        Ok(Some(Reservation {
+            store,
+            credential,
+            epoch,
+            nullifier,
+            committed: false,
+        }))
+    }
+
+    /// Mark the reserved nullifier as spent.
+    pub(crate) fn commit(mut self) -> io::Result<()> {
+        self.store
+            .commit(self.credential, self.epoch, self.nullifier)?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl<'a, S: NullifierStore> Drop for Reservation<'a, S> {
+    fn drop(&mut self) {
+        if !self.committed {
+            self.store
+                .release(self.credential, self.epoch, self.nullifier);
+        }
+    }
+}
+
+/// An in-memory [`NullifierStore`].
+///
+/// Each epoch's nullifier set is dropped as soon as credentials from that
+/// epoch can no longer be rolled over under the store's [`RolloverPolicy`],
+/// according to the store's [`Clock`].
+#[derive(Default, Debug)]
+pub struct MemoryNullifierStore<C = SystemClock> {
+    sets: HashMap<(CredentialType, Epoch), HashSet<[u8; 32]>>,
+    reserved: HashSet<(CredentialType, Epoch, [u8; 32])>,
+    clock: C,
+    policy: RolloverPolicy,
+}
+
+impl MemoryNullifierStore {
+    /// Create an empty nullifier store using the system time.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<C: Clock> MemoryNullifierStore<C> {
+    /// Create an empty nullifier store using `clock`.
+    pub fn with_clock(clock: C) -> Self {
+        MemoryNullifierStore {
+            sets: HashMap::new(),
+            reserved: HashSet::new(),
+            clock,
+            policy: RolloverPolicy::default(),
+        }
+    }
+
+    /// Retain nullifier sets for as long as `policy` accepts rollovers.
+    pub fn with_rollover_policy(mut self, policy: RolloverPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Check whether `nullifier` is in the nullifier set for credentials of
+    /// type `credential` in `epoch`, without modifying the set.
+    pub fn contains(&self, credential: CredentialType, epoch: Epoch, nullifier: &[u8; 32]) -> bool {
+        self.sets
+            .get(&(credential, epoch))
+            .map(|set| set.contains(nullifier))
+            .unwrap_or(false)
+    }
+
+    /// Drop the nullifier sets of every epoch which has expired at `time`.
+    pub fn prune(&mut self, time: DateTime<Utc>) {
+        let policy = self.policy;
+        self.sets
+            .retain(|(_, epoch), _| !policy.has_expired_at(epoch, time));
+        self.reserved
+            .retain(|(_, epoch, _)| !policy.has_expired_at(epoch, time));
+    }
+}
+
+impl<C: Clock> NullifierStore for MemoryNullifierStore<C> {
+    /// Nullifiers for expired epochs are always treated as spent, since
+    /// their nullifier sets may already have been dropped.
+    fn reserve(
+        &mut self,
+        credential: CredentialType,
+        epoch: Epoch,
+        nullifier: [u8; 32],
+    ) -> io::Result<bool> {
+        let now = self.clock.now();
+        self.prune(now);
+        if self.policy.has_expired_at(&epoch, now) || self.contains(credential, epoch, &nullifier) {
+            return Ok(false);
+        }
+        Ok(self.reserved.insert((credential, epoch, nullifier)))
+    }
+
+    fn commit(
+        &mut self,
+        credential: CredentialType,
+        epoch: Epoch,
+        nullifier: [u8; 32],
+    ) -> io::Result<()> {
+        self.reserved.remove(&(credential, epoch, nullifier));
+        self.sets
+            .entry((credential, epoch))
+            .or_default()
+            .insert(nullifier);
+        Ok(())
+    }
+
+    fn release(&mut self, credential: CredentialType, epoch: Epoch, nullifier: [u8; 32]) {
+        self.reserved.remove(&(credential, epoch, nullifier));
+    }
+
+    fn rollover_policy(&self) -> RolloverPolicy {
+        self.policy
+    }
+}
@@ -0,0 +1,263 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+
+use super::{CredentialType, NullifierStore};
+use crate::{Clock, Epoch, EpochParameters, RolloverPolicy, SystemClock};
+
+/// Each record in a log is a single 32-byte nullifier.
+const RECORD_LEN: u64 = 32;
+
+/// The append-only log of spent nullifiers for one credential type and epoch.
+#[derive(Debug)]
+struct EpochLog {
+    file: File,
+    len: u64,
+    nullifiers: HashSet<[u8; 32]>,
+}
+
+impl EpochLog {
+    /// Open (or create) a log, rebuilding its index.
+    ///
+    /// A crash while appending can leave a partial record at the end of the
+    /// log. That nullifier was never acknowledged as recorded, so the
+    /// partial record is truncated away.
+    fn open(path: &Path) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+
+        let len = (bytes.len() as u64 / RECORD_LEN) * RECORD_LEN;
+        if len != bytes.len() as u64 {
+            file.set_len(len)?;
+            file.sync_data()?;
+        }
+
+        let nullifiers = bytes[..len as usize]
+            .chunks(RECORD_LEN as usize)
+            .map(|record| {
+                let mut nullifier = [0u8; 32];
+                nullifier.copy_from_slice(record);
+                nullifier
+            })
+            .collect();
+
+        Ok(EpochLog {
+            file,
+            len,
+            nullifiers,
+        })
+    }
+
+    /// Append a nullifier to the log, returning only once it is durable.
+    fn append(&mut self, nullifier: [u8; 32]) -> io::Result<()> {
+        let result = self
+            .file
+            .write_all(&nullifier)
+            .and_then(|_| self.file.sync_data());
+        if let Err(e) = result {
+            // Discard any partially written record so that later appends stay
+            // aligned; if this also fails, it is truncated on the next open.
+            let _ = self.file.set_len(self.len);
+            return Err(e);
+        }
+        self.len += RECORD_LEN;
+        self.nullifiers.insert(nullifier);
+        Ok(())
+    }
+}
+
+/// A durable, file-backed [`NullifierStore`].
+///
+/// Each credential type and epoch has its own append-only log in the store's
+/// directory, and every nullifier is synced to disk before it is reported as
+/// recorded, so a restarted issuer still rejects nullifiers spent before the
+/// restart. The in-memory index is rebuilt from the logs on
+/// [`open`](FileNullifierStore::open), and each epoch's log is deleted once
+/// credentials from the epoch can no longer be rolled over under the store's
+/// [`RolloverPolicy`], according to the store's [`Clock`].
+/// Reservations are only held in memory, so a restart releases every
+/// uncommitted nullifier.
+#[derive(Debug)]
+pub struct FileNullifierStore<C = SystemClock> {
+    dir: PathBuf,
+    logs: HashMap<(CredentialType, Epoch), EpochLog>,
+    reserved: HashSet<(CredentialType, Epoch, [u8; 32])>,
+    clock: C,
+    policy: RolloverPolicy,
+}
+
+fn log_name(credential: CredentialType, epoch: Epoch) -> String {
+    let credential = match credential {
+        CredentialType::Wallet => "wallet",
+        CredentialType::Token => "token",
+    };
+    format!("{}_{}_{}.log", credential, epoch.params.0, epoch.index)
+}
+
+fn parse_log_name(name: &str) -> Option<(CredentialType, Epoch)> {
+    let mut parts = name.strip_suffix(".log")?.splitn(3, '_');
+    let credential = match parts.next()? {
+        "wallet" => CredentialType::Wallet,
+        "token" => CredentialType::Token,
+        _ => return None,
+    };
+    let duration = parts.next()?.parse().ok()?;
+    let index = parts.next()?.parse().ok()?;
+    let epoch = Epoch {
+        index,
+        params: EpochParameters(duration),
+    };
+    // A stray file must not crash the issuer in the epoch arithmetic.
+    if !epoch.is_well_formed() {
+        return None;
+    }
+    Some((credential, epoch))
+}
+
+impl FileNullifierStore {
+    /// Open the nullifier store in `dir` using the system time, creating the
+    /// directory if needed and rebuilding the index of every epoch's log.
+    pub fn open<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        FileNullifierStore::open_with_clock(dir, SystemClock)
+    }
+}
+
+impl<C: Clock> FileNullifierStore<C> {
+    /// Open the nullifier store in `dir` as in
+    /// [`open`](FileNullifierStore::open), using `clock`.
+    pub fn open_with_clock<P: AsRef<Path>>(dir: P, clock: C) -> io::Result<Self> {
+        FileNullifierStore::open_with_policy(dir, clock, RolloverPolicy::default())
+    }
+
+    /// Open the nullifier store in `dir` as in
+    /// [`open`](FileNullifierStore::open), using `clock` and retaining logs
+    /// for as long as `policy` accepts rollovers.
+    pub fn open_with_policy<P: AsRef<Path>>(
+        dir: P,
+        clock: C,
+        policy: RolloverPolicy,
+    ) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let mut logs = HashMap::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let key = match path.file_name().and_then(|n| n.to_str()) {
+                Some(name) => match parse_log_name(name) {
+                    Some(key) => key,
+                    None => continue,
+                },
+                None => continue,
+            };
+            logs.insert(key, EpochLog::open(&path)?);
+        }
+
+        let mut store = FileNullifierStore {
+            dir,
+            logs,
+            reserved: HashSet::new(),
+            clock,
+            policy,
+        };
+        store.prune(store.clock.now())?;
+        Ok(store)
+    }
+
+    /// Check whether `nullifier` is in the nullifier set for credentials of
+    /// type `credential` in `epoch`, without modifying the set.
+    pub fn contains(&self, credential: CredentialType, epoch: Epoch, nullifier: &[u8; 32]) -> bool {
+        self.logs
+            .get(&(credential, epoch))
+            .map(|log| log.nullifiers.contains(nullifier))
+            .unwrap_or(false)
+    }
+
+    /// Delete the logs of every epoch which has expired at `time`.
+    pub fn prune(&mut self, time: DateTime<Utc>) -> io::Result<()> {
+        let expired: Vec<_> = self
+            .logs
+            .keys()
+            .filter(|(_, epoch)| self.policy.has_expired_at(epoch, time))
+            .cloned()
+            .collect();
+        let policy = self.policy;
+        self.reserved
+            .retain(|(_, epoch, _)| !policy.has_expired_at(epoch, time));
+        if expired.is_empty() {
+            return Ok(());
+        }
+        for (credential, epoch) in expired {
+            self.logs.remove(&(credential, epoch));
+            fs::remove_file(self.dir.join(log_name(credential, epoch)))?;
+        }
+        self.sync_dir()
+    }
+
+    /// Make creation and deletion of logs durable.
+    fn sync_dir(&self) -> io::Result<()> {
+        #[cfg(unix)]
+        File::open(&self.dir)?.sync_all()?;
+        Ok(())
+    }
+}
+
+impl<C: Clock> NullifierStore for FileNullifierStore<C> {
+    /// Nullifiers for expired epochs are always treated as spent, since
+    /// their logs may already have been deleted.
+    fn reserve(
+        &mut self,
+        credential: CredentialType,
+        epoch: Epoch,
+        nullifier: [u8; 32],
+    ) -> io::Result<bool> {
+        let now = self.clock.now();
+        self.prune(now)?;
+        if self.policy.has_expired_at(&epoch, now) || self.contains(credential, epoch, &nullifier) {
+            return Ok(false);
+        }
+        Ok(self.reserved.insert((credential, epoch, nullifier)))
+    }
+
+    /// The nullifier is only released from its reservation once it is
+    /// durable, so a failed commit leaves it reserved until released.
+    fn commit(
+        &mut self,
+        credential: CredentialType,
+        epoch: Epoch,
+        nullifier: [u8; 32],
+    ) -> io::Result<()> {
+        if !self.logs.contains_key(&(credential, epoch)) {
+            let log = EpochLog::open(&self.dir.join(log_name(credential, epoch)))?;
+            self.sync_dir()?;
+            self.logs.insert((credential, epoch), log);
+        }
+        let log = self
+            .logs
+            .get_mut(&(credential, epoch))
+            .expect("log was just inserted");
+
+        if !log.nullifiers.contains(&nullifier) {
+            log.append(nullifier)?;
+        }
+        self.reserved.remove(&(credential, epoch, nullifier));
+        Ok(())
+    }
+
+    fn release(&mut self, credential: CredentialType, epoch: Epoch, nullifier: [u8; 32]) {
+        self.reserved.remove(&(credential, epoch, nullifier));
+    }
+
+    fn rollover_policy(&self) -> RolloverPolicy {
+        self.policy
+    }
+}
@@ -0,0 +1,71 @@
+use chrono::{DateTime, Utc};
+
+use crate::Epoch;
+
+/// An issuer's policy for how old a credential can be and still be rolled
+/// over.
+///
+/// Under the standard key schedule, a credential can be rolled over until
+/// its epoch leaves the Rollover state, two epochs after it was Primary.  A
+/// client which was offline for longer would lose its credential, so an
+/// issuer can instead accept rollovers of credentials up to `max_epochs`
+/// epochs old, mapping a credential in epoch \\(i\\) directly to one in the
+/// current epoch in a single round trip.
+///
+/// The issuer must keep the secrets and nullifier sets of each epoch for as
+/// long as its credentials can be rolled over, so the same policy must be
+/// given to its [`KeyRing`](crate::KeyRing) and nullifier store.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct RolloverPolicy {
+    max_epochs: u32,
+}
+
+impl Default for RolloverPolicy {
+    /// The standard key schedule, which accepts rollovers of credentials in
+    /// the Rollover state but no older.
+    fn default() -> Self {
+        RolloverPolicy { max_epochs: 2 }
+    }
+}
+
+impl RolloverPolicy {
+    /// Accept rollovers of credentials from up to `max_epochs` epochs before
+    /// the current epoch.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_epochs` is less than 2, since the standard key schedule
+    /// always accepts credentials in the Rollover state.
+    pub fn new(max_epochs: u32) -> Self {
+        assert!(max_epochs >= 2, "rollover policy shorter than key schedule");
+        RolloverPolicy { max_epochs }
+    }
+
+    /// The maximum age, in epochs, of a credential which can be rolled over.
+    pub fn max_epochs(&self) -> u32 {
+        self.max_epochs
+    }
+
+    /// Returns `true` if credentials from `epoch` can be rolled over at
+    /// `time`, which is the case from when the epoch is first Active until it
+    /// is `max_epochs` epochs old.
+    pub(crate) fn accepts_at(&self, epoch: &Epoch, time: DateTime<Utc>) -> bool {
+        let age = epoch
+            .params
+            .epoch_at(time)
+            .index
+            .saturating_sub(epoch.index);
+        age >= -1 && age <= i64::from(self.max_epochs)
+    }
+
+    /// Returns `true` if credentials from `epoch` can no longer be rolled
+    /// over at `time`, so that its secrets and nullifier sets can be deleted.
+    pub(crate) fn has_expired_at(&self, epoch: &Epoch, time: DateTime<Utc>) -> bool {
+        epoch
+            .params
+            .epoch_at(time)
+            .index
+            .saturating_sub(epoch.index)
+            > i64::from(self.max_epochs)
+    }
+}
@@ -0,0 +1,1140 @@
+//! Presentation of a credential with a balance, and reissuance of the
+//! credential with the balance changed by a public amount.
+//!
+//! Wallet topup and debit and token spend all follow this protocol.  The
+//! client presents its credential, revealing its nullifier, and proves that
+//! its new balance `m_prime` differs from its balance `m` by the revealed
+//! amount and lies in range.  The issuer then blindly issues a credential
+//! with the new balance and a fresh nullifier under the same key.
+//!
+//! The protocols differ only in the credential presented, the protocol label
+//! and whether the revealed amount is added or deducted, which are given by
+//! a [`Protocol`].  Each protocol module is a thin wrapper around the
+//! generic [`Request`], [`Response`] and [`AwaitingResponse`] defined here.
+//!
+//! The public inputs are bound to the transcript as described in
+//! [`crate::transcript`] before the proofs are created or verified.
+//! Rollover, in [`crate::rollover`], and token purchase build on the
+//! lower-level [`present`] and [`Presentation`] directly.  A token purchase
+//! also issues a token alongside the reissued wallet, with a
+//! [`BlindedNullifier`] encrypted under the presentation's key and a public
+//! balance.
+
+use std::fmt;
+use std::marker::PhantomData;
+
+use curve25519_dalek::{
+    ristretto::{CompressedRistretto, RistrettoPoint},
+    scalar::Scalar,
+    traits::MultiscalarMul,
+};
+use merlin::Transcript;
+use rand_core::{CryptoRng, RngCore};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use zeroize::Zeroize;
+
+use crate::{
+    constants, encoding, Clock, CredentialType, DeploymentId, Epoch, Error, IssuerContext,
+    NullifierStore, Reservation, Tag, TranscriptProtocol, PROTOCOL_VERSION,
+};
+
+mod proofs {
+    define_proof! {
+        client,
+        "presentation::client",
+        (
+            d,
+            m,
+            m_prime,
+            m_blinding,
+            n_prime,
+            minus_r_Q,
+            r_m,
+            r_n
+        ),
+        (
+            D,
+            Enc_m_prime_B_0,
+            Enc_m_prime_B_1,
+            Enc_n_prime_B_0,
+            Enc_n_prime_B_1,
+            P,
+            V,
+            Com_m,
+            Com_m_prime
+        ),
+        (
+            B,
+            B_blinding,
+            X_1
+        )
+        :
+        D = (d * B),
+        Enc_n_prime_B_0 = (r_n * B),
+        Enc_n_prime_B_1 = (n_prime * B + r_n * D),
+        Enc_m_prime_B_0 = (r_m * B),
+        Enc_m_prime_B_1 = (m_prime * B + r_m * D),
+        Com_m = (m * P + m_blinding * B_blinding),
+        Com_m_prime = (m_prime * P + m_blinding * B_blinding),
+        V = (m_blinding * X_1 + minus_r_Q * B)
+    }
+
+    define_proof! {
+        issuer,
+        "presentation::issuer",
+        (
+            b,
+            r,
+            x_0,
+            x_1,
+            x_2,
+            x_0_blinding,
+            t_1,
+            t_2
+        ),
+        (
+            P,
+            D,
+            Enc_m_prime_B_0,
+            Enc_m_prime_B_1,
+            Enc_n_prime_B_0,
+            Enc_n_prime_B_1,
+            Enc_Q_0,
+            Enc_Q_1,
+            T_1_a,
+            T_1_b,
+            T_2_a,
+            T_2_b
+        ),
+        (X_0, X_1, X_2, B, B_blinding)
+        :
+        X_0 = (x_0 * B + x_0_blinding * B_blinding),
+        X_1 = (x_1 * B_blinding),
+        X_2 = (x_2 * B_blinding),
+        P = (b * B),
+        T_1_a = (b * X_1),
+        T_1_b = (t_1 * B_blinding),
+        T_2_a = (b * X_2),
+        T_2_b = (t_2 * B_blinding),
+        Enc_Q_0 = (r * B + t_1 * Enc_m_prime_B_0 + t_2 * Enc_n_prime_B_0),
+        Enc_Q_1 = (x_0 * P + r * D + t_1 * Enc_m_prime_B_1 + t_2 * Enc_n_prime_B_1)
+    }
+
+    define_proof! {
+        nullifier,
+        "presentation::nullifier",
+        (n, r),
+        (D, Enc_nB_0, Enc_nB_1),
+        (B)
+        :
+        Enc_nB_0 = (r * B),
+        Enc_nB_1 = (n * B + r * D)
+    }
+
+    define_proof! {
+        issuance,
+        "presentation::issuance",
+        (b, r, x_0, x_1, x_2, x_0_blinding, t_2),
+        (P, mP, D, Enc_nB_0, Enc_nB_1, Enc_Q_0, Enc_Q_1, T_2_a, T_2_b),
+        (X_0, X_1, X_2, B, B_blinding)
+        :
+        X_0 = (x_0 * B + x_0_blinding * B_blinding),
+        X_1 = (x_1 * B_blinding),
+        X_2 = (x_2 * B_blinding),
+        P = (b * B),
+        T_2_a = (b * X_2),
+        T_2_b = (t_2 * B_blinding),
+        Enc_Q_0 = (r * B + t_2 * Enc_nB_0),
+        Enc_Q_1 = (x_0 * P + x_1 * mP + r * D + t_2 * Enc_nB_1)
+    }
+}
+
+/// The public key of a wallet or token issuer.
+#[allow(non_snake_case)]
+pub struct PublicKey<'a> {
+    pub(crate) X_0: &'a RistrettoPoint,
+    pub(crate) X_1: &'a RistrettoPoint,
+    pub(crate) X_2: &'a RistrettoPoint,
+}
+
+/// The secret key of a wallet or token issuer, with its public key.
+pub struct Key<'a> {
+    pub(crate) x_0: &'a Scalar,
+    pub(crate) x_1: &'a Scalar,
+    pub(crate) x_2: &'a Scalar,
+    pub(crate) x_0_blinding: &'a Scalar,
+    pub(crate) public: PublicKey<'a>,
+}
+
+/// A client's presentation of a credential, with the encrypted attributes
+/// of the credential to be reissued.
+#[derive(Clone, Serialize, Deserialize)]
+#[allow(non_snake_case)]
+pub(crate) struct Presentation {
+    D: CompressedRistretto,
+    Enc_n_prime_B: (CompressedRistretto, CompressedRistretto),
+    Enc_m_prime_B: (CompressedRistretto, CompressedRistretto),
+    Com_m: CompressedRistretto,
+    P: CompressedRistretto,
+    C_Q: CompressedRistretto,
+    proof: proofs::client::CompactProof,
+    range_proof: Option<bulletproofs::RangeProof>,
+}
+
+/// The client's secrets for a [`Presentation`], needed to verify the
+/// issuer's [`Reissue`].
+#[derive(Clone, Serialize, Deserialize)]
+#[allow(non_snake_case)]
+pub(crate) struct Opening {
+    n_prime: Scalar,
+    d: Scalar,
+    D: RistrettoPoint,
+    V: CompressedRistretto,
+}
+
+impl Drop for Opening {
+    fn drop(&mut self) {
+        self.n_prime.zeroize();
+        self.d.zeroize();
+    }
+}
+
+/// The issuer's encrypted tag for the reissued credential, with its proof.
+#[derive(Clone, Serialize, Deserialize)]
+#[allow(non_snake_case)]
+pub(crate) struct Reissue {
+    P: CompressedRistretto,
+    Enc_Q: (CompressedRistretto, CompressedRistretto),
+    T_1: CompressedRistretto,
+    T_2: CompressedRistretto,
+    proof: proofs::issuer::CompactProof,
+}
+
+/// The nullifier of a credential issued alongside a [`Presentation`],
+/// encrypted under the presentation's key `D`, with a proof that the
+/// encryption is well-formed.
+#[derive(Clone, Serialize, Deserialize)]
+#[allow(non_snake_case)]
+pub(crate) struct BlindedNullifier {
+    Enc_nB: (CompressedRistretto, CompressedRistretto),
+    proof: proofs::nullifier::CompactProof,
+}
+
+/// The issuer's encrypted tag for a credential with a public balance and a
+/// [`BlindedNullifier`], with its proof.
+#[derive(Clone, Serialize, Deserialize)]
+#[allow(non_snake_case)]
+pub(crate) struct Issuance {
+    P: CompressedRistretto,
+    Enc_Q: (CompressedRistretto, CompressedRistretto),
+    T_2: CompressedRistretto,
+    proof: proofs::issuance::CompactProof,
+}
+
+/// Present the credential with tag `tag` and balance `m`, proving that the
+/// new balance `m_prime` lies in the range `[0, 2^range_proof_bits)`.
+///
+/// The caller reveals the amount by which `m_prime` differs from `m`, and
+/// must bind it to `transcript` first.  A rollover, which keeps the balance,
+/// passes no `range_proof_bits` and makes no rangeproof, since the balance
+/// was already in range when the credential was issued.
+#[allow(non_snake_case)]
+pub(crate) fn present<R: RngCore + CryptoRng>(
+    tag: &Tag,
+    m: u64,
+    m_prime: u64,
+    public_key: &PublicKey,
+    range_proof_bits: Option<usize>,
+    transcript: &mut Transcript,
+    mut rng: R,
+) -> Result<(Presentation, Opening), Error> {
+    let B: &RistrettoPoint = &constants::B;
+
+    let tag = tag.randomize(&mut rng);
+
+    let m_prime_value = m_prime;
+    let m = Scalar::from(m);
+    let m_prime = Scalar::from(m_prime);
+    let m_blinding = Scalar::random(&mut rng);
+
+    // The commitment to the new balance m_prime has bases P, B_blinding, so
+    // we construct custom pedersen commitment generators to pass to the
+    // bulletproofs library.
+    let pc_gens = bulletproofs::PedersenGens {
+        B: tag.P,
+        B_blinding: constants::PG.B_blinding,
+    };
+    let Com_m = pc_gens.commit(m, m_blinding);
+    let Com_m_prime = pc_gens.commit(m_prime, m_blinding);
+
+    let r_Q = Scalar::random(&mut rng);
+    let C_Q = tag.Q + r_Q * B;
+
+    let V = m_blinding * public_key.X_1 - r_Q * B;
+
+    let n_prime = Scalar::random(&mut rng);
+    let d = Scalar::random(&mut rng);
+    let D = d * B;
+
+    let r_m = Scalar::random(&mut rng);
+    let Enc_m_prime_B = (r_m * B, (m_prime + r_m * d) * B);
+
+    let r_n = Scalar::random(&mut rng);
+    let Enc_n_prime_B = (r_n * B, (n_prime + r_n * d) * B);
+
+    use proofs::client::*;
+
+    let (proof, points) = prove_compact(
+        transcript,
+        ProveAssignments {
+            d: &d,
+            m: &m,
+            m_prime: &m_prime,
+            m_blinding: &m_blinding,
+            n_prime: &n_prime,
+            minus_r_Q: &(-r_Q),
+            r_m: &r_m,
+            r_n: &r_n,
+            D: &D,
+            Enc_n_prime_B_0: &Enc_n_prime_B.0,
+            Enc_n_prime_B_1: &Enc_n_prime_B.1,
+            Enc_m_prime_B_0: &Enc_m_prime_B.0,
+            Enc_m_prime_B_1: &Enc_m_prime_B.1,
+            P: &tag.P,
+            V: &V,
+            Com_m: &Com_m,
+            Com_m_prime: &Com_m_prime,
+            B,
+            B_blinding: &constants::B_BLINDING,
+            X_1: public_key.X_1,
+        },
+    );
+
+    // The issuer computes Com_m_prime from Com_m and the revealed amount, so
+    // the rangeproof shows that the change does not overflow or underflow
+    // the balance.
+    let range_proof = match range_proof_bits {
+        Some(bits) => Some(
+            bulletproofs::RangeProof::prove_single(
+                &constants::BP_GENS,
+                &pc_gens,
+                transcript,
+                m_prime_value,
+                &m_blinding,
+                bits,
+            )
+            .map_err(|_| Error::RangeProof)?
+            .0,
+        ),
+        None => None,
+    };
+
+    Ok((
+        Presentation {
+            D: points.D,
+            Enc_n_prime_B: (points.Enc_n_prime_B_0, points.Enc_n_prime_B_1),
+            Enc_m_prime_B: (points.Enc_m_prime_B_0, points.Enc_m_prime_B_1),
+            Com_m: points.Com_m,
+            P: points.P,
+            C_Q: C_Q.compress(),
+            proof,
+            range_proof,
+        },
+        Opening {
+            n_prime,
+            d,
+            D,
+            V: points.V,
+        },
+    ))
+}
+
+impl Presentation {
+    /// Compute the presentation point `V` for the revealed nullifier `n`
+    /// under `key`, which the client's proof refers to.
+    #[allow(non_snake_case)]
+    pub(crate) fn presentation_point(
+        &self,
+        key: &Key,
+        n: &Scalar,
+    ) -> Result<CompressedRistretto, Error> {
+        let Com_m = self.Com_m.decompress().ok_or(Error::Decompression)?;
+        let C_Q = self.C_Q.decompress().ok_or(Error::Decompression)?;
+        let P = self.P.decompress().ok_or(Error::Decompression)?;
+        let V =
+            RistrettoPoint::multiscalar_mul(&[key.x_0 + key.x_2 * n, *key.x_1], &[P, Com_m]) - C_Q;
+        Ok(V.compress())
+    }
+
+    /// Verify the client's proofs, given the presentation point `V` and the
+    /// signed amount `delta` added to the balance, leaving `transcript` in
+    /// the state the client left it in after proving.
+    ///
+    /// A rangeproof is required exactly when `range_proof_bits` is given.
+    #[allow(non_snake_case)]
+    pub(crate) fn verify(
+        &self,
+        public_key: &PublicKey,
+        delta: Scalar,
+        range_proof_bits: Option<usize>,
+        V: &CompressedRistretto,
+        transcript: &mut Transcript,
+    ) -> Result<(), Error> {
+        let Com_m = self.Com_m.decompress().ok_or(Error::Decompression)?;
+        let P = self.P.decompress().ok_or(Error::Decompression)?;
+
+        let Com_m_prime = (Com_m + P * delta).compress();
+
+        proofs::client::verify_compact(
+            &self.proof,
+            transcript,
+            proofs::client::VerifyAssignments {
+                B: &constants::B_COMPRESSED,
+                B_blinding: &constants::B_BLINDING_COMPRESSED,
+                Com_m: &self.Com_m,
+                Com_m_prime: &Com_m_prime,
+                D: &self.D,
+                Enc_n_prime_B_0: &self.Enc_n_prime_B.0,
+                Enc_n_prime_B_1: &self.Enc_n_prime_B.1,
+                Enc_m_prime_B_0: &self.Enc_m_prime_B.0,
+                Enc_m_prime_B_1: &self.Enc_m_prime_B.1,
+                P: &self.P,
+                V,
+                X_1: &public_key.X_1.compress(),
+            },
+        )
+        .map_err(|_| Error::ClientProof)?;
+
+        let (range_proof, bits) = match (&self.range_proof, range_proof_bits) {
+            (Some(range_proof), Some(bits)) => (range_proof, bits),
+            (None, None) => return Ok(()),
+            _ => return Err(Error::RangeProof),
+        };
+        let pc_gens = bulletproofs::PedersenGens {
+            B: P,
+            B_blinding: constants::PG.B_blinding,
+        };
+        range_proof
+            .verify_single(
+                &constants::BP_GENS,
+                &pc_gens,
+                transcript,
+                &Com_m_prime,
+                bits,
+            )
+            .map_err(|_| Error::RangeProof)
+    }
+
+    /// Blindly issue a credential under `key` for the encrypted new balance
+    /// and nullifier, once the presentation has been verified.
+    #[allow(non_snake_case)]
+    pub(crate) fn reissue<R: RngCore + CryptoRng>(
+        &self,
+        key: &Key,
+        transcript: &mut Transcript,
+        mut rng: R,
+    ) -> Result<Reissue, Error> {
+        let B: &RistrettoPoint = &constants::B;
+
+        let D = self.D.decompress().ok_or(Error::Decompression)?;
+        let Enc_n_prime_B = (
+            self.Enc_n_prime_B
+                .0
+                .decompress()
+                .ok_or(Error::Decompression)?,
+            self.Enc_n_prime_B
+                .1
+                .decompress()
+                .ok_or(Error::Decompression)?,
+        );
+        let Enc_m_prime_B = (
+            self.Enc_m_prime_B
+                .0
+                .decompress()
+                .ok_or(Error::Decompression)?,
+            self.Enc_m_prime_B
+                .1
+                .decompress()
+                .ok_or(Error::Decompression)?,
+        );
+
+        let b = Scalar::random(&mut rng);
+        let r = Scalar::random(&mut rng);
+
+        let P = b * B;
+        let Enc_Q = (
+            RistrettoPoint::multiscalar_mul(
+                &[r, b * key.x_1, b * key.x_2],
+                &[*B, Enc_m_prime_B.0, Enc_n_prime_B.0],
+            ),
+            RistrettoPoint::multiscalar_mul(
+                &[r, *key.x_0, b * key.x_1, b * key.x_2],
+                &[D, P, Enc_m_prime_B.1, Enc_n_prime_B.1],
+            ),
+        );
+
+        use proofs::issuer::*;
+        let t_1 = b * key.x_1;
+        let T_1 = b * key.public.X_1;
+        let t_2 = b * key.x_2;
+        let T_2 = b * key.public.X_2;
+        let (proof, points) = prove_compact(
+            transcript,
+            ProveAssignments {
+                b: &b,
+                r: &r,
+                x_0: key.x_0,
+                x_1: key.x_1,
+                x_2: key.x_2,
+                x_0_blinding: key.x_0_blinding,
+                t_1: &t_1,
+                t_2: &t_2,
+                P: &P,
+                D: &D,
+                Enc_m_prime_B_0: &Enc_m_prime_B.0,
+                Enc_m_prime_B_1: &Enc_m_prime_B.1,
+                Enc_n_prime_B_0: &Enc_n_prime_B.0,
+                Enc_n_prime_B_1: &Enc_n_prime_B.1,
+                Enc_Q_0: &Enc_Q.0,
+                Enc_Q_1: &Enc_Q.1,
+                T_1_a: &T_1,
+                T_1_b: &T_1,
+                T_2_a: &T_2,
+                T_2_b: &T_2,
+                X_0: key.public.X_0,
+                X_1: key.public.X_1,
+                X_2: key.public.X_2,
+                B,
+                B_blinding: &constants::B_BLINDING,
+            },
+        );
+
+        Ok(Reissue {
+            P: points.P,
+            Enc_Q: (points.Enc_Q_0, points.Enc_Q_1),
+            T_1: points.T_1_a,
+            T_2: points.T_2_a,
+            proof,
+        })
+    }
+
+    /// Verify the client's proof that `blinded` is well-formed, leaving
+    /// `transcript` in the state the client left it in after proving.
+    pub(crate) fn verify_blinded_nullifier(
+        &self,
+        blinded: &BlindedNullifier,
+        transcript: &mut Transcript,
+    ) -> Result<(), Error> {
+        proofs::nullifier::verify_compact(
+            &blinded.proof,
+            transcript,
+            proofs::nullifier::VerifyAssignments {
+                D: &self.D,
+                Enc_nB_0: &blinded.Enc_nB.0,
+                Enc_nB_1: &blinded.Enc_nB.1,
+                B: &constants::B_COMPRESSED,
+            },
+        )
+        .map_err(|_| Error::ClientProof)
+    }
+
+    /// Blindly issue a credential under `key` with the public balance `m`
+    /// and the nullifier encrypted in `blinded`, once the presentation and
+    /// `blinded` have been verified.
+    #[allow(non_snake_case)]
+    pub(crate) fn issue<R: RngCore + CryptoRng>(
+        &self,
+        blinded: &BlindedNullifier,
+        key: &Key,
+        m: u64,
+        transcript: &mut Transcript,
+        mut rng: R,
+    ) -> Result<Issuance, Error> {
+        let B: &RistrettoPoint = &constants::B;
+
+        let D = self.D.decompress().ok_or(Error::Decompression)?;
+        let Enc_nB = (
+            blinded.Enc_nB.0.decompress().ok_or(Error::Decompression)?,
+            blinded.Enc_nB.1.decompress().ok_or(Error::Decompression)?,
+        );
+
+        let b = Scalar::random(&mut rng);
+        let r = Scalar::random(&mut rng);
+
+        let m = Scalar::from(m);
+        let P = b * B;
+        let mP = m * P;
+        let Enc_Q = (
+            RistrettoPoint::multiscalar_mul(&[r, b * key.x_2], &[*B, Enc_nB.0]),
+            RistrettoPoint::multiscalar_mul(
+                &[key.x_0 + key.x_1 * m, b * key.x_2, r],
+                &[P, Enc_nB.1, D],
+            ),
+        );
+
+        use proofs::issuance::*;
+        let t_2 = b * key.x_2;
+        let T_2 = b * key.public.X_2;
+        let (proof, points) = prove_compact(
+            transcript,
+            ProveAssignments {
+                b: &b,
+                r: &r,
+                x_0: key.x_0,
+                x_1: key.x_1,
+                x_2: key.x_2,
+                x_0_blinding: key.x_0_blinding,
+                t_2: &t_2,
+                P: &P,
+                mP: &mP,
+                D: &D,
+                Enc_nB_0: &Enc_nB.0,
+                Enc_nB_1: &Enc_nB.1,
+                Enc_Q_0: &Enc_Q.0,
+                Enc_Q_1: &Enc_Q.1,
+                T_2_a: &T_2,
+                T_2_b: &T_2,
+                X_0: key.public.X_0,
+                X_1: key.public.X_1,
+                X_2: key.public.X_2,
+                B,
+                B_blinding: &constants::B_BLINDING,
+            },
+        );
+
+        Ok(Issuance {
+            P: points.P,
+            Enc_Q: (points.Enc_Q_0, points.Enc_Q_1),
+            T_2: points.T_2_a,
+            proof,
+        })
+    }
+}
+
+impl Opening {
+    /// Encrypt a fresh nullifier for a credential issued alongside the
+    /// presentation, returning the nullifier and its encryption.
+    #[allow(non_snake_case)]
+    pub(crate) fn blind_nullifier<R: RngCore + CryptoRng>(
+        &self,
+        transcript: &mut Transcript,
+        mut rng: R,
+    ) -> (Scalar, BlindedNullifier) {
+        let B: &RistrettoPoint = &constants::B;
+
+        let n = Scalar::random(&mut rng);
+        let r = Scalar::random(&mut rng);
+        let Enc_nB = (r * B, (n + r * self.d) * B);
+
+        use proofs::nullifier::*;
+        let (proof, points) = prove_compact(
+            transcript,
+            ProveAssignments {
+                n: &n,
+                r: &r,
+                D: &self.D,
+                Enc_nB_0: &Enc_nB.0,
+                Enc_nB_1: &Enc_nB.1,
+                B,
+            },
+        );
+
+        (
+            n,
+            BlindedNullifier {
+                Enc_nB: (points.Enc_nB_0, points.Enc_nB_1),
+                proof,
+            },
+        )
+    }
+
+    /// Verify the issuer's proof for `issuance` of a credential with the
+    /// public balance `m` and the nullifier encrypted in `blinded`, and
+    /// decrypt the tag of the issued credential.
+    #[allow(non_snake_case)]
+    pub(crate) fn verify_issuance(
+        &self,
+        blinded: &BlindedNullifier,
+        issuance: &Issuance,
+        m: u64,
+        public_key: &PublicKey,
+        transcript: &mut Transcript,
+    ) -> Result<Tag, Error> {
+        let P = issuance.P.decompress().ok_or(Error::Decompression)?;
+        let mP = P * Scalar::from(m);
+
+        use proofs::issuance::*;
+        verify_compact(
+            &issuance.proof,
+            transcript,
+            VerifyAssignments {
+                P: &issuance.P,
+                mP: &mP.compress(),
+                D: &self.D.compress(),
+                Enc_nB_0: &blinded.Enc_nB.0,
+                Enc_nB_1: &blinded.Enc_nB.1,
+                Enc_Q_0: &issuance.Enc_Q.0,
+                Enc_Q_1: &issuance.Enc_Q.1,
+                T_2_a: &issuance.T_2,
+                T_2_b: &issuance.T_2,
+                X_0: &public_key.X_0.compress(),
+                X_1: &public_key.X_1.compress(),
+                X_2: &public_key.X_2.compress(),
+                B: &constants::B_COMPRESSED,
+                B_blinding: &constants::B_BLINDING_COMPRESSED,
+            },
+        )
+        .map_err(|_| Error::IssuerProof)?;
+
+        let Enc_Q = (
+            issuance.Enc_Q.0.decompress().ok_or(Error::Decompression)?,
+            issuance.Enc_Q.1.decompress().ok_or(Error::Decompression)?,
+        );
+
+        let Q = Enc_Q.1 - self.d * Enc_Q.0;
+
+        Ok(Tag { P, Q })
+    }
+
+    /// The nullifier of the reissued credential.
+    pub(crate) fn n_prime(&self) -> Scalar {
+        self.n_prime
+    }
+
+    /// The presentation point `V` the client's proof refers to.
+    pub(crate) fn presentation_point(&self) -> &CompressedRistretto {
+        &self.V
+    }
+
+    /// Verify the issuer's proof for `reissue` in response to
+    /// `presentation`, and decrypt the tag of the reissued credential.
+    #[allow(non_snake_case)]
+    pub(crate) fn verify_reissue(
+        &self,
+        presentation: &Presentation,
+        reissue: &Reissue,
+        public_key: &PublicKey,
+        transcript: &mut Transcript,
+    ) -> Result<Tag, Error> {
+        let P = reissue.P.decompress().ok_or(Error::Decompression)?;
+
+        use proofs::issuer::*;
+        verify_compact(
+            &reissue.proof,
+            transcript,
+            VerifyAssignments {
+                P: &reissue.P,
+                D: &self.D.compress(),
+                Enc_m_prime_B_0: &presentation.Enc_m_prime_B.0,
+                Enc_m_prime_B_1: &presentation.Enc_m_prime_B.1,
+                Enc_n_prime_B_0: &presentation.Enc_n_prime_B.0,
+                Enc_n_prime_B_1: &presentation.Enc_n_prime_B.1,
+                Enc_Q_0: &reissue.Enc_Q.0,
+                Enc_Q_1: &reissue.Enc_Q.1,
+                T_1_a: &reissue.T_1,
+                T_1_b: &reissue.T_1,
+                T_2_a: &reissue.T_2,
+                T_2_b: &reissue.T_2,
+                X_0: &public_key.X_0.compress(),
+                X_1: &public_key.X_1.compress(),
+                X_2: &public_key.X_2.compress(),
+                B: &constants::B_COMPRESSED,
+                B_blinding: &constants::B_BLINDING_COMPRESSED,
+            },
+        )
+        .map_err(|_| Error::IssuerProof)?;
+
+        let Enc_Q = (
+            reissue.Enc_Q.0.decompress().ok_or(Error::Decompression)?,
+            reissue.Enc_Q.1.decompress().ok_or(Error::Decompression)?,
+        );
+
+        let Q = Enc_Q.1 - self.d * Enc_Q.0;
+
+        Ok(Tag { P, Q })
+    }
+}
+
+/// A credential with a balance, which is presented and reissued by a
+/// [`Protocol`].
+pub trait Credential: Sized {
+    /// The issuer parameters the credential is issued under.
+    type Parameters: CredentialParameters;
+    /// The issuer secrets matching [`Credential::Parameters`].
+    type Secrets: CredentialSecrets<Parameters = Self::Parameters>;
+    /// The nullifier set the credential's nullifiers are recorded in.
+    const TYPE: CredentialType;
+    /// The name of the credential's rollover protocol, bound to the
+    /// transcript and the response cache.
+    const ROLLOVER_LABEL: &'static [u8];
+
+    /// The epoch of the parameters the credential was issued under.
+    fn epoch(&self) -> Epoch;
+    /// The balance of the credential.
+    fn balance(&self) -> u64;
+    /// The nullifier revealed when the credential is presented.
+    fn nullifier(&self) -> &Scalar;
+    /// The MAC tag on the credential.
+    fn tag(&self) -> &Tag;
+    /// Build a reissued credential from its attributes and tag.
+    fn reissued(epoch: Epoch, balance: u64, nullifier: Scalar, tag: Tag) -> Self;
+}
+
+/// The public parameters of a credential issuer.
+pub trait CredentialParameters: Copy + Serialize + DeserializeOwned {
+    /// The epoch these parameters are for.
+    fn epoch(&self) -> Epoch;
+    /// The issuer public key in these parameters.
+    fn public_key(&self) -> PublicKey<'_>;
+    /// The bit size of the rangeproofs on credential balances.
+    fn range_proof_bits(&self) -> usize;
+    /// Check whether `value` is a valid balance for these parameters.
+    fn in_range(&self, value: u64) -> bool {
+        self.range_proof_bits() >= 64 || value >> self.range_proof_bits() == 0
+    }
+    /// Append these parameters to `transcript` under `label`.
+    fn append_to_transcript(&self, label: &'static [u8], transcript: &mut Transcript);
+    /// Check that these parameters belong to `deployment`.
+    fn check_deployment(&self, deployment: &DeploymentId) -> Result<(), Error>;
+}
+
+/// The secrets of a credential issuer.
+pub trait CredentialSecrets {
+    /// The public parameters matching these secrets.
+    type Parameters;
+
+    /// The public parameters matching these secrets.
+    fn parameters(&self) -> &Self::Parameters;
+    /// The key used to present and reissue credentials.
+    fn key(&self) -> Key<'_>;
+}
+
+/// A protocol which presents a credential and reissues it with its balance
+/// changed by a revealed amount.
+pub trait Protocol: Clone {
+    /// The credential presented and reissued.
+    type Credential: Credential;
+    /// The name of the protocol, bound to the transcript and the response
+    /// cache.
+    const LABEL: &'static [u8];
+    /// The transcript label of the revealed amount.
+    const AMOUNT_LABEL: &'static [u8];
+    /// Whether the revealed amount is deducted from the balance, rather than
+    /// added to it.
+    const DEDUCTS: bool;
+}
+
+/// Wallet topup, adding the revealed amount to a wallet.
+#[derive(Clone)]
+pub enum Topup {}
+
+/// Wallet debit, deducting the revealed amount from a wallet.
+#[derive(Clone)]
+pub enum Debit {}
+
+/// Token spend, deducting the revealed value from a token.
+#[derive(Clone)]
+pub enum Spend {}
+
+type ParametersOf<P> = <<P as Protocol>::Credential as Credential>::Parameters;
+type SecretsOf<P> = <<P as Protocol>::Credential as Credential>::Secrets;
+
+/// A request presenting a credential in the protocol `P`.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct Request<P> {
+    version: u16,
+    epoch: Epoch,
+    amount: u64,
+    n: Scalar,
+    presentation: Presentation,
+    #[serde(skip)]
+    protocol: PhantomData<P>,
+}
+
+impl_wire_format!(Request<P: Protocol>);
+
+/// Bind the public inputs of the protocol `P` to `transcript`: the issuer
+/// parameters, then the revealed amount and the revealed nullifier `n`.
+fn append_public_inputs<P: Protocol>(
+    transcript: &mut Transcript,
+    deployment: &DeploymentId,
+    version: u16,
+    parameters: &ParametersOf<P>,
+    amount: u64,
+    n: &Scalar,
+) {
+    transcript.dom_sep(deployment, version, P::LABEL);
+    parameters.append_to_transcript(b"parameters", transcript);
+    transcript.append_amount(P::AMOUNT_LABEL, amount);
+    transcript.append_nullifier(b"n", n);
+}
+
+impl<P: Protocol> Request<P> {
+    /// The epoch of the credential being presented.
+    pub fn epoch(&self) -> Epoch {
+        self.epoch
+    }
+
+    /// Verify the client's proofs, given the presentation point `V`, leaving
+    /// `transcript` in the state the client left it in after proving.
+    #[allow(non_snake_case)]
+    fn verify_proofs(
+        &self,
+        deployment: &DeploymentId,
+        parameters: &ParametersOf<P>,
+        V: &CompressedRistretto,
+        transcript: &mut Transcript,
+    ) -> Result<(), Error> {
+        append_public_inputs::<P>(
+            transcript,
+            deployment,
+            self.version,
+            parameters,
+            self.amount,
+            &self.n,
+        );
+        let delta = Scalar::from(self.amount);
+        self.presentation.verify(
+            &parameters.public_key(),
+            if P::DEDUCTS { -delta } else { delta },
+            Some(parameters.range_proof_bits()),
+            V,
+            transcript,
+        )
+    }
+}
+
+/// A response to a [`Request`] in the protocol `P`.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct Response<P> {
+    version: u16,
+    reissue: Reissue,
+    #[serde(skip)]
+    protocol: PhantomData<P>,
+}
+
+impl_wire_format!(Response<P: Protocol>);
+
+/// State held by the client while awaiting a response in the protocol `P`.
+#[derive(Clone)]
+pub struct AwaitingResponse<P: Protocol> {
+    transcript: Transcript,
+    state: State<P>,
+}
+
+impl<P: Protocol> fmt::Debug for AwaitingResponse<P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "AwaitingResponse(..)")
+    }
+}
+
+/// The part of an [`AwaitingResponse`] which is stored by
+/// [`AwaitingResponse::to_bytes`].
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(bound = "")]
+struct State<P: Protocol> {
+    deployment: DeploymentId,
+    parameters: ParametersOf<P>,
+    m_prime: u64,
+    opening: Opening,
+    request: Request<P>,
+}
+
+impl<P: Protocol> Drop for State<P> {
+    fn drop(&mut self) {
+        self.m_prime.zeroize();
+    }
+}
+
+/// Present `credential` in the protocol `P`, revealing `amount`, and
+/// generate the request together with the client state needed to verify a
+/// response with the reissued credential.
+pub(crate) fn request<P: Protocol, R: RngCore + CryptoRng>(
+    credential: P::Credential,
+    amount: u64,
+    parameters: &ParametersOf<P>,
+    deployment: &DeploymentId,
+    mut transcript: Transcript,
+    rng: R,
+) -> Result<(AwaitingResponse<P>, Request<P>), Error> {
+    if credential.epoch() != parameters.epoch() {
+        return Err(Error::WrongEpoch);
+    }
+
+    let m = credential.balance();
+    let m_prime = if P::DEDUCTS {
+        m.checked_sub(amount).ok_or(Error::InsufficientBalance)?
+    } else {
+        m + amount
+    };
+
+    append_public_inputs::<P>(
+        &mut transcript,
+        deployment,
+        PROTOCOL_VERSION,
+        parameters,
+        amount,
+        credential.nullifier(),
+    );
+
+    // The rangeproof shows that the new balance does not overflow or
+    // underflow.  Token balances use a smaller rangeproof than wallet
+    // balances, reducing the issuer's verification work for each spend.
+    let (presentation, opening) = present(
+        credential.tag(),
+        m,
+        m_prime,
+        &parameters.public_key(),
+        Some(parameters.range_proof_bits()),
+        &mut transcript,
+        rng,
+    )?;
+
+    let request = Request {
+        version: PROTOCOL_VERSION,
+        epoch: credential.epoch(),
+        amount,
+        n: *credential.nullifier(),
+        presentation,
+        protocol: PhantomData,
+    };
+
+    Ok((
+        AwaitingResponse {
+            transcript,
+            state: State {
+                deployment: deployment.clone(),
+                parameters: *parameters,
+                m_prime,
+                opening,
+                request: request.clone(),
+            },
+        },
+        request,
+    ))
+}
+
+/// Process a request in the protocol `P`, presenting the client's credential
+/// and issuing a new credential with the revealed amount added or deducted.
+///
+/// The revealed nullifier is checked against the nullifier set for the
+/// request's epoch in the issuer's nullifier store, and only added to it once
+/// the request has been fully verified.  If the issuer has a response cache,
+/// a retry of an identical request gets the cached response.
+pub(crate) fn respond<P: Protocol, R: RngCore + CryptoRng>(
+    secrets: &SecretsOf<P>,
+    request: Request<P>,
+    issuer: &mut IssuerContext<impl Clock, impl NullifierStore>,
+    transcript: Transcript,
+    rng: R,
+) -> Result<Response<P>, Error> {
+    let bytes = request.to_bytes();
+    issuer.respond(
+        P::LABEL,
+        <P::Credential as Credential>::TYPE,
+        secrets.parameters().epoch(),
+        request.n.to_bytes(),
+        &bytes,
+        |issuer| process(secrets, request, issuer, transcript, rng),
+    )
+}
+
+#[allow(non_snake_case)]
+fn process<P: Protocol, R: RngCore + CryptoRng>(
+    secrets: &SecretsOf<P>,
+    request: Request<P>,
+    issuer: &mut IssuerContext<impl Clock, impl NullifierStore>,
+    mut transcript: Transcript,
+    rng: R,
+) -> Result<Response<P>, Error> {
+    let deployment = issuer.deployment;
+    deployment.check_version(request.version)?;
+
+    let params = secrets.parameters();
+    params.check_deployment(deployment)?;
+
+    if params.epoch() != request.epoch {
+        return Err(Error::WrongEpoch);
+    }
+    request.epoch.check_presentable(issuer.clock.now())?;
+
+    // The nullifier is reserved until the response is ready, and released
+    // if verification fails before then.
+    let credential = <P::Credential as Credential>::TYPE;
+    let reservation = Reservation::new(
+        issuer.nullifiers,
+        credential,
+        request.epoch,
+        request.n.to_bytes(),
+    )?
+    .ok_or(Error::NullifierReuse(credential))?;
+
+    let key = secrets.key();
+    let V = request.presentation.presentation_point(&key, &request.n)?;
+    request.verify_proofs(deployment, params, &V, &mut transcript)?;
+
+    let reissue = request.presentation.reissue(&key, &mut transcript, rng)?;
+
+    reservation.commit()?;
+
+    Ok(Response {
+        version: request.version,
+        reissue,
+        protocol: PhantomData,
+    })
+}
+
+impl<P: Protocol> AwaitingResponse<P> {
+    /// The request this state is awaiting a response to, which can be sent
+    /// again after a restart.
+    pub fn request(&self) -> &Request<P> {
+        &self.state.request
+    }
+
+    /// Encode this state for storage while the request is in flight.
+    ///
+    /// The encoding contains the client's secrets for the new credential, so
+    /// it should be stored encrypted.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encoding::to_bytes(&self.state)
+    }
+
+    /// Restore a state encoded with [`AwaitingResponse::to_bytes`].
+    ///
+    /// The transcript is not stored, so it is rebuilt by replaying the
+    /// client's proofs on `transcript`, which must be constructed in the same
+    /// way as the transcript passed with the original request.
+    pub fn from_bytes(bytes: &[u8], mut transcript: Transcript) -> Result<Self, Error> {
+        let state: State<P> = encoding::from_bytes(bytes)?;
+        state.request.verify_proofs(
+            &state.deployment,
+            &state.parameters,
+            state.opening.presentation_point(),
+            &mut transcript,
+        )?;
+        Ok(AwaitingResponse { transcript, state })
+    }
+
+    /// Verify a response and obtain the reissued credential.
+    pub fn verify_response(mut self, response: Response<P>) -> Result<P::Credential, Error> {
+        if response.version != self.state.request.version {
+            return Err(Error::ProtocolVersion(response.version));
+        }
+
+        let tag = self.state.opening.verify_reissue(
+            &self.state.request.presentation,
+            &response.reissue,
+            &self.state.parameters.public_key(),
+            &mut self.transcript,
+        )?;
+
+        Ok(P::Credential::reissued(
+            self.state.parameters.epoch(),
+            self.state.m_prime,
+            self.state.opening.n_prime(),
+            tag,
+        ))
+    }
+}
@@ -0,0 +1,405 @@
+//! Rollover of a credential to the parameters of a later epoch, keeping its
+//! balance.
+//!
+//! Wallet and token rollover both follow this protocol, differing only in
+//! the [`Credential`] rolled over.  The client presents its credential under
+//! the old parameters as in [`crate::presentation`], with a balance change
+//! of zero and no rangeproof, and the issuer reissues it under the new
+//! parameters.
+
+use std::fmt;
+use std::marker::PhantomData;
+
+use curve25519_dalek::{ristretto::CompressedRistretto, scalar::Scalar};
+use merlin::Transcript;
+use rand_core::{CryptoRng, RngCore};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroize;
+
+use crate::presentation::{
+    self, Credential, CredentialParameters, CredentialSecrets, Opening, Presentation, Reissue,
+};
+use crate::{
+    encoding, Clock, DeploymentId, Epoch, EpochState, Error, IssuerContext, NullifierStore,
+    Reservation, TranscriptProtocol, PROTOCOL_VERSION,
+};
+
+/// A request for rollover of a credential of type `C`.
+#[derive(Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct Request<C> {
+    version: u16,
+    epoch: Epoch,
+    new_epoch: Epoch,
+    n: Scalar,
+    presentation: Presentation,
+    #[serde(skip)]
+    credential: PhantomData<fn() -> C>,
+}
+
+impl_wire_format!(Request<C: Credential>);
+
+// Credentials are not `Clone`, so the messages and states implement it
+// without requiring it of `C`.
+impl<C> Clone for Request<C> {
+    fn clone(&self) -> Self {
+        Request {
+            version: self.version,
+            epoch: self.epoch,
+            new_epoch: self.new_epoch,
+            n: self.n,
+            presentation: self.presentation.clone(),
+            credential: PhantomData,
+        }
+    }
+}
+
+/// Bind the public inputs of a rollover to `transcript`: the old and new
+/// issuer parameters, then the revealed nullifier `n`.
+fn append_public_inputs<C: Credential>(
+    transcript: &mut Transcript,
+    deployment: &DeploymentId,
+    version: u16,
+    old_parameters: &C::Parameters,
+    new_parameters: &C::Parameters,
+    n: &Scalar,
+) {
+    transcript.dom_sep(deployment, version, C::ROLLOVER_LABEL);
+    old_parameters.append_to_transcript(b"old_parameters", transcript);
+    new_parameters.append_to_transcript(b"new_parameters", transcript);
+    transcript.append_nullifier(b"n", n);
+}
+
+impl<C: Credential> Request<C> {
+    /// The epoch of the credential being rolled over.
+    pub fn epoch(&self) -> Epoch {
+        self.epoch
+    }
+
+    /// The epoch of the new credential.
+    pub fn new_epoch(&self) -> Epoch {
+        self.new_epoch
+    }
+
+    /// Verify the client's proof, given the presentation point `V`, leaving
+    /// `transcript` in the state the client left it in after proving.
+    #[allow(non_snake_case)]
+    fn verify_proofs(
+        &self,
+        deployment: &DeploymentId,
+        old_parameters: &C::Parameters,
+        new_parameters: &C::Parameters,
+        V: &CompressedRistretto,
+        transcript: &mut Transcript,
+    ) -> Result<(), Error> {
+        append_public_inputs::<C>(
+            transcript,
+            deployment,
+            self.version,
+            old_parameters,
+            new_parameters,
+            &self.n,
+        );
+        self.presentation.verify(
+            &old_parameters.public_key(),
+            Scalar::zero(),
+            None,
+            V,
+            transcript,
+        )
+    }
+}
+
+/// State held by the client while awaiting a rollover response.
+pub struct AwaitingResponse<C: Credential> {
+    transcript: Transcript,
+    state: State<C>,
+}
+
+impl<C: Credential> Clone for AwaitingResponse<C> {
+    fn clone(&self) -> Self {
+        AwaitingResponse {
+            transcript: self.transcript.clone(),
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<C: Credential> fmt::Debug for AwaitingResponse<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "AwaitingResponse(..)")
+    }
+}
+
+/// The part of an [`AwaitingResponse`] which is stored by
+/// [`AwaitingResponse::to_bytes`].
+#[derive(Serialize, Deserialize)]
+#[serde(bound = "")]
+struct State<C: Credential> {
+    deployment: DeploymentId,
+    old_parameters: C::Parameters,
+    new_parameters: C::Parameters,
+    m: u64,
+    opening: Opening,
+    request: Request<C>,
+}
+
+impl<C: Credential> Clone for State<C> {
+    fn clone(&self) -> Self {
+        State {
+            deployment: self.deployment.clone(),
+            old_parameters: self.old_parameters,
+            new_parameters: self.new_parameters,
+            m: self.m,
+            opening: self.opening.clone(),
+            request: self.request.clone(),
+        }
+    }
+}
+
+impl<C: Credential> Drop for State<C> {
+    fn drop(&mut self) {
+        self.m.zeroize();
+    }
+}
+
+/// Present `credential` under `old_parameters` for reissue under
+/// `new_parameters`, and generate the rollover request together with the
+/// client state needed to verify a response with the new credential.
+pub(crate) fn request<C: Credential, R: RngCore + CryptoRng>(
+    credential: C,
+    old_parameters: &C::Parameters,
+    new_parameters: &C::Parameters,
+    deployment: &DeploymentId,
+    mut transcript: Transcript,
+    rng: R,
+) -> Result<(AwaitingResponse<C>, Request<C>), Error> {
+    if credential.epoch() != old_parameters.epoch() {
+        return Err(Error::WrongEpoch);
+    }
+
+    let m = credential.balance();
+    if !new_parameters.in_range(m) {
+        return Err(Error::ValueOutOfRange);
+    }
+
+    append_public_inputs::<C>(
+        &mut transcript,
+        deployment,
+        PROTOCOL_VERSION,
+        old_parameters,
+        new_parameters,
+        credential.nullifier(),
+    );
+
+    let (presentation, opening) = presentation::present(
+        credential.tag(),
+        m,
+        m,
+        &old_parameters.public_key(),
+        None,
+        &mut transcript,
+        rng,
+    )?;
+
+    let request = Request {
+        version: PROTOCOL_VERSION,
+        epoch: old_parameters.epoch(),
+        new_epoch: new_parameters.epoch(),
+        n: *credential.nullifier(),
+        presentation,
+        credential: PhantomData,
+    };
+
+    Ok((
+        AwaitingResponse {
+            transcript,
+            state: State {
+                deployment: deployment.clone(),
+                old_parameters: *old_parameters,
+                new_parameters: *new_parameters,
+                m,
+                opening,
+                request: request.clone(),
+            },
+        },
+        request,
+    ))
+}
+
+/// A response to a rollover request.
+#[derive(Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct Response<C> {
+    version: u16,
+    reissue: Reissue,
+    #[serde(skip)]
+    credential: PhantomData<fn() -> C>,
+}
+
+impl_wire_format!(Response<C: Credential>);
+
+impl<C> Clone for Response<C> {
+    fn clone(&self) -> Self {
+        Response {
+            version: self.version,
+            reissue: self.reissue.clone(),
+            credential: PhantomData,
+        }
+    }
+}
+
+impl<C: Credential> Request<C> {
+    /// Process a rollover request, presenting the client's credential under
+    /// `old_secret` and issuing a new credential with the same balance under
+    /// `new_secret`.
+    ///
+    /// The old epoch may be older than the Rollover state if the issuer's
+    /// rollover policy accepts longer rollovers.  The new epoch must be
+    /// Active or Primary.
+    ///
+    /// If the issuer has a response cache, a retry of an identical request
+    /// gets the cached response.
+    pub fn rollover<R: RngCore + CryptoRng>(
+        &self,
+        old_secret: &C::Secrets,
+        new_secret: &C::Secrets,
+        issuer: &mut IssuerContext<impl Clock, impl NullifierStore>,
+        transcript: Transcript,
+        rng: R,
+    ) -> Result<Response<C>, Error> {
+        issuer.respond(
+            C::ROLLOVER_LABEL,
+            C::TYPE,
+            old_secret.parameters().epoch(),
+            self.n.to_bytes(),
+            &self.to_bytes(),
+            |issuer| self.process_rollover(old_secret, new_secret, issuer, transcript, rng),
+        )
+    }
+
+    #[allow(non_snake_case)]
+    fn process_rollover<R: RngCore + CryptoRng>(
+        &self,
+        old_secret: &C::Secrets,
+        new_secret: &C::Secrets,
+        issuer: &mut IssuerContext<impl Clock, impl NullifierStore>,
+        mut transcript: Transcript,
+        rng: R,
+    ) -> Result<Response<C>, Error> {
+        let deployment = issuer.deployment;
+        deployment.check_version(self.version)?;
+
+        let old_parameters = old_secret.parameters();
+        let new_parameters = new_secret.parameters();
+        old_parameters.check_deployment(deployment)?;
+        new_parameters.check_deployment(deployment)?;
+
+        // The epochs in the request are chosen by the client, so only the
+        // epochs of the secrets are used from here on.
+        if old_parameters.epoch() != self.epoch || new_parameters.epoch() != self.new_epoch {
+            return Err(Error::WrongEpoch);
+        }
+
+        let now = issuer.clock.now();
+        if !issuer
+            .nullifiers
+            .rollover_policy()
+            .accepts_at(&old_parameters.epoch(), now)
+        {
+            return Err(Error::OldEpochState);
+        }
+        match new_parameters.epoch().state_at(now) {
+            EpochState::Active => {}
+            EpochState::Primary => {}
+            _ => return Err(Error::NewEpochState),
+        }
+
+        // The nullifier is reserved until the response is ready, and released
+        // if verification fails before then.
+        let reservation = Reservation::new(
+            issuer.nullifiers,
+            C::TYPE,
+            old_parameters.epoch(),
+            self.n.to_bytes(),
+        )?
+        .ok_or(Error::NullifierReuse(C::TYPE))?;
+
+        let V = self
+            .presentation
+            .presentation_point(&old_secret.key(), &self.n)?;
+        self.verify_proofs(
+            deployment,
+            old_parameters,
+            new_parameters,
+            &V,
+            &mut transcript,
+        )?;
+
+        let reissue = self
+            .presentation
+            .reissue(&new_secret.key(), &mut transcript, rng)?;
+
+        reservation.commit()?;
+
+        Ok(Response {
+            version: self.version,
+            reissue,
+            credential: PhantomData,
+        })
+    }
+}
+
+impl<C: Credential> AwaitingResponse<C> {
+    /// The request this state is awaiting a response to, which can be sent
+    /// again after a restart.
+    pub fn request(&self) -> &Request<C> {
+        &self.state.request
+    }
+
+    /// Encode this state for storage while the request is in flight.
+    ///
+    /// The encoding contains the client's secrets for the new credential, so
+    /// it should be stored encrypted.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encoding::to_bytes(&self.state)
+    }
+
+    /// Restore a state encoded with [`AwaitingResponse::to_bytes`].
+    ///
+    /// The transcript is not stored, so it is rebuilt by replaying the
+    /// client's proofs on `transcript`, which must be constructed in the same
+    /// way as the transcript passed with the original request.
+    pub fn from_bytes(bytes: &[u8], mut transcript: Transcript) -> Result<Self, Error> {
+        let state: State<C> = encoding::from_bytes(bytes)?;
+        state.request.verify_proofs(
+            &state.deployment,
+            &state.old_parameters,
+            &state.new_parameters,
+            state.opening.presentation_point(),
+            &mut transcript,
+        )?;
+        Ok(AwaitingResponse { transcript, state })
+    }
+
+    /// Verify a rollover response and obtain the new credential.
+    pub fn verify_response(mut self, response: Response<C>) -> Result<C, Error> {
+        if response.version != self.state.request.version {
+            return Err(Error::ProtocolVersion(response.version));
+        }
+
+        let tag = self.state.opening.verify_reissue(
+            &self.state.request.presentation,
+            &response.reissue,
+            &self.state.new_parameters.public_key(),
+            &mut self.transcript,
+        )?;
+
+        Ok(C::reissued(
+            self.state.new_parameters.epoch(),
+            self.state.m,
+            self.state.opening.n_prime(),
+            tag,
+        ))
+    }
+}
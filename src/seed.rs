@@ -0,0 +1,80 @@
+use std::fmt;
+
+use curve25519_dalek::scalar::Scalar;
+use hkdf::Hkdf;
+use rand_core::{CryptoRng, RngCore};
+use sha2::Sha512;
+use zeroize::Zeroize;
+
+use crate::{CredentialType, Epoch};
+
+/// The HKDF salt, which separates secrets derived by Danake from any other
+/// use of the same seed.
+const SALT: &[u8] = b"danake master seed v1";
+
+/// A long-term master seed from which an issuer's secrets for every epoch
+/// and credential type are derived.
+///
+/// Only the seed needs to be backed up: a replacement issuer can regenerate
+/// the secrets for any epoch from it.
+#[derive(Clone)]
+pub struct MasterSeed([u8; 32]);
+
+impl MasterSeed {
+    /// Generate a random master seed.
+    pub fn generate<R: RngCore + CryptoRng>(mut rng: R) -> Self {
+        let mut seed = [0u8; 32];
+        rng.fill_bytes(&mut seed);
+        MasterSeed(seed)
+    }
+
+    /// Use `bytes` as a master seed.
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        MasterSeed(bytes)
+    }
+
+    /// The bytes of this seed, for backing it up.
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    /// Derive the four secret scalars for `credential` in `epoch`, using
+    /// HKDF-SHA512 with the credential type and epoch as the info string.
+    pub(crate) fn derive_scalars(&self, credential: CredentialType, epoch: Epoch) -> [Scalar; 4] {
+        let mut info = Vec::with_capacity(32);
+        info.extend_from_slice(b"danake issuer secrets ");
+        info.extend_from_slice(match credential {
+            CredentialType::Wallet => &b"wallet"[..],
+            CredentialType::Token => &b"token"[..],
+        });
+        info.extend_from_slice(&epoch.params.0.to_le_bytes());
+        info.extend_from_slice(&epoch.index.to_le_bytes());
+
+        let mut okm = [0u8; 4 * 64];
+        Hkdf::<Sha512>::new(Some(SALT), &self.0)
+            .expand(&info, &mut okm)
+            .expect("output length is within the HKDF limit");
+
+        let mut scalars = [Scalar::zero(); 4];
+        let mut wide = [0u8; 64];
+        for (scalar, bytes) in scalars.iter_mut().zip(okm.chunks(64)) {
+            wide.copy_from_slice(bytes);
+            *scalar = Scalar::from_bytes_mod_order_wide(&wide);
+        }
+        wide.zeroize();
+        okm[..].zeroize();
+        scalars
+    }
+}
+
+impl Drop for MasterSeed {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl fmt::Debug for MasterSeed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MasterSeed(..)")
+    }
+}
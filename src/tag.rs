@@ -1,9 +1,19 @@
-use curve25519_dalek::{ristretto::RistrettoPoint, scalar::Scalar};
+use std::ptr;
+use std::sync::atomic::{self, Ordering};
+
+use curve25519_dalek::{
+    ristretto::RistrettoPoint,
+    scalar::Scalar,
+    traits::{Identity, IsIdentity},
+};
 use rand_core::{CryptoRng, RngCore};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroize;
 
 /// A rerandomizable MAC tag which attests to the integrity of some message.
+#[derive(Serialize, Deserialize)]
 #[allow(non_snake_case)]
-pub(crate) struct Tag {
+pub struct Tag {
     pub(crate) P: RistrettoPoint,
     pub(crate) Q: RistrettoPoint,
 }
@@ -17,4 +27,47 @@ impl Tag {
             Q: self.Q * r,
         }
     }
+
+    /// Returns `true` unless either point of the tag is the identity, which
+    /// no issuer ever produces.
+    pub(crate) fn is_well_formed(&self) -> bool {
+        !self.P.is_identity() && !self.Q.is_identity()
+    }
+}
+
+impl Zeroize for Tag {
+    /// Overwrite both points with the identity.
+    ///
+    /// `RistrettoPoint` does not implement `Zeroize`, so this uses volatile
+    /// writes followed by a compiler fence, as `zeroize` does for its own
+    /// types, to keep the writes from being optimized away.
+    fn zeroize(&mut self) {
+        // SAFETY: the pointers come from mutable references, so they are
+        // valid and aligned, and `RistrettoPoint` has no destructor which
+        // overwriting could skip.
+        unsafe {
+            ptr::write_volatile(&mut self.P, RistrettoPoint::identity());
+            ptr::write_volatile(&mut self.Q, RistrettoPoint::identity());
+        }
+        atomic::compiler_fence(Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zeroize_clears_both_points() {
+        let mut rng = rand::thread_rng();
+        let mut tag = Tag {
+            P: RistrettoPoint::random(&mut rng),
+            Q: RistrettoPoint::random(&mut rng),
+        };
+        assert!(tag.is_well_formed());
+
+        tag.zeroize();
+        assert!(tag.P.is_identity());
+        assert!(tag.Q.is_identity());
+    }
 }
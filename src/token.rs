@@ -0,0 +1,68 @@
+use std::fmt;
+
+use curve25519_dalek::scalar::Scalar;
+use zeroize::Zeroize;
+
+use crate::presentation::Credential;
+use crate::{CredentialType, Epoch, Tag};
+
+/// A token credential.
+pub struct Token {
+    epoch: Epoch,
+    t: u64,
+    n: Scalar,
+    tag: Tag,
+}
+
+impl Credential for Token {
+    type Parameters = Parameters;
+    type Secrets = Secrets;
+    const TYPE: CredentialType = CredentialType::Token;
+    const ROLLOVER_LABEL: &'static [u8] = b"token::rollover";
+
+    fn epoch(&self) -> Epoch {
+        self.epoch
+    }
+
+    fn balance(&self) -> u64 {
+        self.t
+    }
+
+    fn nullifier(&self) -> &Scalar {
+        &self.n
+    }
+
+    fn tag(&self) -> &Tag {
+        &self.tag
+    }
+
+    fn reissued(epoch: Epoch, t: u64, n: Scalar, tag: Tag) -> Self {
+        Token { epoch, t, n, tag }
+    }
+}
+
+impl Drop for Token {
+    fn drop(&mut self) {
+        self.t.zeroize();
+        self.n.zeroize();
+        self.tag.zeroize();
+    }
+}
+
+impl fmt::Debug for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Token {{ epoch: {:?}, .. }}", self.epoch)
+    }
+}
+
+mod keys;
+pub use keys::{Parameters, Secrets};
+
+/// Purchase protocol states and messages.
+pub mod purchase;
+
+/// Spend protocol states and messages.
+pub mod spend;
+
+/// Rollover protocol states and messages.
+pub mod rollover;
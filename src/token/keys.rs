@@ -0,0 +1,291 @@
+use std::fmt;
+
+use bulletproofs::PedersenGens;
+
+use curve25519_dalek::ristretto::RistrettoPoint;
+use curve25519_dalek::scalar::Scalar;
+
+use merlin::Transcript;
+use rand_core::{CryptoRng, RngCore};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroize;
+
+use crate::keyfile::{self, KeyFileKey};
+use crate::presentation::{CredentialParameters, CredentialSecrets, Key, PublicKey};
+use crate::{CredentialType, DeploymentId, Epoch, Error, MasterSeed, TranscriptProtocol};
+
+/// Public parameters for a token issuer for a particular epoch.
+///
+/// Token parameters are distinct from wallet parameters, and their epoch is
+/// computed from the token [`EpochParameters`](crate::EpochParameters), so
+/// the token epoch duration can be shorter than the wallet epoch duration.
+///
+/// They also fix the bit size of the rangeproofs used for token balances,
+/// which is smaller than the 64-bit rangeproofs used for wallet balances.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[allow(non_snake_case)]
+pub struct Parameters {
+    pub(crate) X_0: RistrettoPoint,
+    pub(crate) X_1: RistrettoPoint,
+    pub(crate) X_2: RistrettoPoint,
+    pub(crate) epoch: Epoch,
+    pub(crate) range_proof_bits: usize,
+}
+
+#[derive(Clone)]
+pub(crate) struct Inner {
+    pub(crate) x_0: Scalar,
+    pub(crate) x_1: Scalar,
+    pub(crate) x_2: Scalar,
+    pub(crate) x_0_blinding: Scalar,
+    pub(crate) epoch: Epoch,
+    pub(crate) range_proof_bits: usize,
+}
+
+/// Secret key material for a token issuer.
+///
+/// Held by the issuer and used to issue and verify token credentials.
+#[derive(Clone)]
+pub struct Secrets {
+    pub(crate) inner: Inner,
+    pub(crate) cached_params: Parameters,
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        self.x_0.zeroize();
+        self.x_1.zeroize();
+        self.x_2.zeroize();
+        self.x_0_blinding.zeroize();
+    }
+}
+
+impl fmt::Debug for Secrets {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Secrets {{ epoch: {:?}, .. }}", self.inner.epoch)
+    }
+}
+
+impl Inner {
+    fn parameters(&self) -> Parameters {
+        let pg = PedersenGens::default();
+        Parameters {
+            X_0: pg.commit(self.x_0, self.x_0_blinding),
+            X_1: pg.B_blinding * self.x_1,
+            X_2: pg.B_blinding * self.x_2,
+            epoch: self.epoch,
+            range_proof_bits: self.range_proof_bits,
+        }
+    }
+}
+
+impl Parameters {
+    /// The token epoch these parameters are for.
+    pub fn epoch(&self) -> Epoch {
+        self.epoch
+    }
+}
+
+impl Secrets {
+    /// Generate token issuer secrets for the given token epoch, with token
+    /// balances proved to lie in the range `[0, 2^range_proof_bits)`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `range_proof_bits` is not one of 8, 16, 32, or 64.
+    pub fn new<R: RngCore + CryptoRng>(
+        epoch: Epoch,
+        range_proof_bits: usize,
+        mut rng: R,
+    ) -> Secrets {
+        assert!(
+            [8, 16, 32, 64].contains(&range_proof_bits),
+            "unsupported rangeproof bit size"
+        );
+        let inner = Inner {
+            epoch,
+            range_proof_bits,
+            x_0: Scalar::random(&mut rng),
+            x_1: Scalar::random(&mut rng),
+            x_2: Scalar::random(&mut rng),
+            x_0_blinding: Scalar::random(&mut rng),
+        };
+        Secrets {
+            cached_params: inner.parameters(),
+            inner,
+        }
+    }
+
+    /// Derive the token issuer secrets for the given token epoch from `seed`,
+    /// with token balances proved to lie in the range
+    /// `[0, 2^range_proof_bits)`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `range_proof_bits` is not one of 8, 16, 32, or 64.
+    pub fn derive(seed: &MasterSeed, epoch: Epoch, range_proof_bits: usize) -> Secrets {
+        assert!(
+            [8, 16, 32, 64].contains(&range_proof_bits),
+            "unsupported rangeproof bit size"
+        );
+        let scalars = seed.derive_scalars(CredentialType::Token, epoch);
+        Secrets::from_scalars(epoch, range_proof_bits, scalars)
+    }
+
+    /// Export these secrets as a key file encrypted under `key`, together
+    /// with their epoch, rangeproof size, and public parameters.
+    pub fn to_key_file<R: RngCore + CryptoRng>(&self, key: KeyFileKey, rng: R) -> Vec<u8> {
+        keyfile::export_secrets(self, key, rng)
+    }
+
+    /// Import secrets from a key file written by [`Secrets::to_key_file`].
+    ///
+    /// Fails with [`Error::ParametersMismatch`] if the public parameters
+    /// stored in the file are not those of the stored secrets.
+    pub fn from_key_file(file: &[u8], key: KeyFileKey) -> Result<Secrets, Error> {
+        keyfile::import_secrets(file, key)
+    }
+
+    /// Build secrets for `epoch` from the scalars `x_0`, `x_1`, `x_2`, and
+    /// `x_0_blinding`, without checking `range_proof_bits`.
+    pub(crate) fn from_scalars(
+        epoch: Epoch,
+        range_proof_bits: usize,
+        scalars: [Scalar; 4],
+    ) -> Secrets {
+        let [x_0, x_1, x_2, x_0_blinding] = scalars;
+        let inner = Inner {
+            epoch,
+            range_proof_bits,
+            x_0,
+            x_1,
+            x_2,
+            x_0_blinding,
+        };
+        Secrets {
+            cached_params: inner.parameters(),
+            inner,
+        }
+    }
+
+    /// The scalars `x_0`, `x_1`, `x_2`, and `x_0_blinding`.
+    pub(crate) fn scalars(&self) -> [Scalar; 4] {
+        let inner = &self.inner;
+        [inner.x_0, inner.x_1, inner.x_2, inner.x_0_blinding]
+    }
+}
+
+impl<'a> From<&'a Secrets> for Parameters {
+    fn from(secret: &'a Secrets) -> Parameters {
+        secret.cached_params
+    }
+}
+
+impl CredentialParameters for Parameters {
+    fn epoch(&self) -> Epoch {
+        self.epoch
+    }
+
+    fn public_key(&self) -> PublicKey<'_> {
+        PublicKey {
+            X_0: &self.X_0,
+            X_1: &self.X_1,
+            X_2: &self.X_2,
+        }
+    }
+
+    fn range_proof_bits(&self) -> usize {
+        self.range_proof_bits
+    }
+
+    fn append_to_transcript(&self, label: &'static [u8], transcript: &mut Transcript) {
+        transcript.append_token_parameters(label, self);
+    }
+
+    fn check_deployment(&self, deployment: &DeploymentId) -> Result<(), Error> {
+        deployment.check_token_parameters(self)
+    }
+}
+
+impl CredentialSecrets for Secrets {
+    type Parameters = Parameters;
+
+    fn parameters(&self) -> &Parameters {
+        &self.cached_params
+    }
+
+    fn key(&self) -> Key<'_> {
+        Key {
+            x_0: &self.inner.x_0,
+            x_1: &self.inner.x_1,
+            x_2: &self.inner.x_2,
+            x_0_blinding: &self.inner.x_0_blinding,
+            public: self.cached_params.public_key(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::EpochParameters;
+
+    fn epochs() -> (Epoch, Epoch) {
+        let params = EpochParameters::from(std::time::Duration::from_secs(86400));
+        let now = chrono::Utc::now();
+        (
+            params.epoch_at(now),
+            params.epoch_at(now + chrono::Duration::days(1)),
+        )
+    }
+
+    /// The parameters of derived secrets are determined by the seed and
+    /// epoch, and are those recomputed from the secret scalars.
+    #[test]
+    fn parameters_are_derived_consistently() {
+        let (epoch, _) = epochs();
+        let seed = MasterSeed::from_bytes([7; 32]);
+
+        let secrets = Secrets::derive(&seed, epoch, 32);
+        let params = Parameters::from(&secrets);
+        assert_eq!(params, Parameters::from(&Secrets::derive(&seed, epoch, 32)));
+        assert_eq!(params, secrets.inner.parameters());
+        assert_eq!(
+            params,
+            Parameters::from(&Secrets::from_scalars(epoch, 32, secrets.scalars()))
+        );
+        assert_eq!(params.epoch(), epoch);
+        assert_eq!(params.range_proof_bits(), 32);
+    }
+
+    #[test]
+    fn parameters_survive_serde_round_trip() {
+        let (epoch, _) = epochs();
+        let params = Parameters::from(&Secrets::new(epoch, 16, rand::thread_rng()));
+
+        let bytes = bincode::serialize(&params).unwrap();
+        let decoded: Parameters = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(decoded, params);
+    }
+
+    /// The same seed gives independent keys in different epochs.
+    #[test]
+    fn epochs_have_different_keys() {
+        let (epoch, next_epoch) = epochs();
+        let seed = MasterSeed::from_bytes([7; 32]);
+
+        let secrets = Secrets::derive(&seed, epoch, 32);
+        let next_secrets = Secrets::derive(&seed, next_epoch, 32);
+        for (x, next_x) in secrets.scalars().iter().zip(next_secrets.scalars().iter()) {
+            assert_ne!(x, next_x);
+        }
+
+        let params = Parameters::from(&secrets);
+        let next_params = Parameters::from(&next_secrets);
+        assert_ne!(params.X_0, next_params.X_0);
+        assert_ne!(params.X_1, next_params.X_1);
+        assert_ne!(params.X_2, next_params.X_2);
+        assert_ne!(params.epoch(), next_params.epoch());
+    }
+}
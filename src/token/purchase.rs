@@ -0,0 +1,381 @@
+use std::fmt;
+
+use curve25519_dalek::{ristretto::CompressedRistretto, scalar::Scalar};
+use merlin::Transcript;
+use rand_core::{CryptoRng, RngCore};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroize;
+
+use crate::presentation::{
+    self, BlindedNullifier, CredentialParameters, CredentialSecrets, Issuance, Opening,
+    Presentation, Reissue,
+};
+use crate::{
+    encoding, wallet, wallet::Wallet, Clock, CredentialType, DeploymentId, Epoch, EpochState,
+    Error, IssuerContext, NullifierStore, Reservation, TranscriptProtocol, PROTOCOL_VERSION,
+};
+
+use super::keys::{Parameters, Secrets};
+use super::Token;
+
+/// A request to purchase a token with value from a wallet.
+///
+/// The wallet is presented as in a debit of the token value, and the token
+/// nullifier is encrypted under the presentation's key, so that the issuer
+/// can issue the token blindly alongside the new wallet.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Request {
+    version: u16,
+    epoch: Epoch,
+    token_epoch: Epoch,
+    t: u64,
+    n: Scalar,
+    presentation: Presentation,
+    n_t: BlindedNullifier,
+}
+
+impl_wire_format!(Request);
+
+/// Bind the public inputs of a purchase to `transcript`: the wallet and
+/// token issuer parameters, then the revealed token value `t` and the
+/// revealed wallet nullifier `n`.
+fn append_public_inputs(
+    transcript: &mut Transcript,
+    deployment: &DeploymentId,
+    version: u16,
+    wallet_parameters: &wallet::Parameters,
+    token_parameters: &Parameters,
+    t: u64,
+    n: &Scalar,
+) {
+    transcript.dom_sep(deployment, version, b"token::purchase");
+    transcript.append_wallet_parameters(b"wallet_parameters", wallet_parameters);
+    transcript.append_token_parameters(b"token_parameters", token_parameters);
+    transcript.append_amount(b"t", t);
+    transcript.append_nullifier(b"n", n);
+}
+
+impl Request {
+    /// The epoch of the wallet being presented.
+    pub fn epoch(&self) -> Epoch {
+        self.epoch
+    }
+
+    /// The token epoch of the token being purchased.
+    pub fn token_epoch(&self) -> Epoch {
+        self.token_epoch
+    }
+
+    /// Verify the client's proofs, given the presentation point `V`, leaving
+    /// `transcript` in the state the client left it in after proving.
+    #[allow(non_snake_case)]
+    fn verify_proofs(
+        &self,
+        deployment: &DeploymentId,
+        wallet_parameters: &wallet::Parameters,
+        token_parameters: &Parameters,
+        V: &CompressedRistretto,
+        transcript: &mut Transcript,
+    ) -> Result<(), Error> {
+        append_public_inputs(
+            transcript,
+            deployment,
+            self.version,
+            wallet_parameters,
+            token_parameters,
+            self.t,
+            &self.n,
+        );
+        self.presentation.verify(
+            &wallet_parameters.public_key(),
+            -Scalar::from(self.t),
+            Some(wallet_parameters.range_proof_bits()),
+            V,
+            transcript,
+        )?;
+        self.presentation
+            .verify_blinded_nullifier(&self.n_t, transcript)
+    }
+}
+
+/// State held by the client while awaiting a token purchase response.
+#[derive(Clone)]
+pub struct AwaitingResponse {
+    transcript: Transcript,
+    state: State,
+}
+
+impl fmt::Debug for AwaitingResponse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "AwaitingResponse(..)")
+    }
+}
+
+/// The part of an [`AwaitingResponse`] which is stored by
+/// [`AwaitingResponse::to_bytes`].
+#[derive(Clone, Serialize, Deserialize)]
+struct State {
+    deployment: DeploymentId,
+    wallet_parameters: wallet::Parameters,
+    token_parameters: Parameters,
+    w_prime: u64,
+    n_t: Scalar,
+    opening: Opening,
+    request: Request,
+}
+
+impl Drop for State {
+    fn drop(&mut self) {
+        self.w_prime.zeroize();
+        self.n_t.zeroize();
+    }
+}
+
+impl Wallet {
+    /// Request purchase of a token with value `t`, consuming this credential
+    /// and generating a purchase request message together with the client
+    /// state needed to verify a response with a new wallet credential with
+    /// balance `w - t` and a new token credential with balance `t`.
+    pub fn request_token_purchase<R: RngCore + CryptoRng>(
+        self,
+        t: u64,
+        wallet_parameters: &wallet::Parameters,
+        token_parameters: &Parameters,
+        deployment: &DeploymentId,
+        mut transcript: Transcript,
+        mut rng: R,
+    ) -> Result<(AwaitingResponse, Request), Error> {
+        if self.epoch != wallet_parameters.epoch {
+            return Err(Error::WrongEpoch);
+        }
+
+        if !token_parameters.in_range(t) {
+            return Err(Error::ValueOutOfRange);
+        }
+
+        let w_prime = self.w.checked_sub(t).ok_or(Error::InsufficientBalance)?;
+
+        append_public_inputs(
+            &mut transcript,
+            deployment,
+            PROTOCOL_VERSION,
+            wallet_parameters,
+            token_parameters,
+            t,
+            &self.n,
+        );
+
+        let (presentation, opening) = presentation::present(
+            &self.tag,
+            self.w,
+            w_prime,
+            &wallet_parameters.public_key(),
+            Some(wallet_parameters.range_proof_bits()),
+            &mut transcript,
+            &mut rng,
+        )?;
+        let (n_t, blinded_n_t) = opening.blind_nullifier(&mut transcript, &mut rng);
+
+        let request = Request {
+            version: PROTOCOL_VERSION,
+            epoch: self.epoch,
+            token_epoch: token_parameters.epoch,
+            t,
+            n: self.n,
+            presentation,
+            n_t: blinded_n_t,
+        };
+
+        Ok((
+            AwaitingResponse {
+                transcript,
+                state: State {
+                    deployment: deployment.clone(),
+                    wallet_parameters: *wallet_parameters,
+                    token_parameters: *token_parameters,
+                    w_prime,
+                    n_t,
+                    opening,
+                    request: request.clone(),
+                },
+            },
+            request,
+        ))
+    }
+}
+
+/// A response to a token purchase request.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Response {
+    version: u16,
+    reissue: Reissue,
+    issuance: Issuance,
+}
+
+impl_wire_format!(Response);
+
+impl Request {
+    /// Process a token purchase request, presenting the client's wallet under
+    /// `wallet_secret` and issuing a new wallet under `wallet_secret` together
+    /// with a new token under `token_secret`.
+    ///
+    /// This function is solely responsible for the purchase itself and not for
+    /// application policy (e.g., checking that the token value is valid).
+    ///
+    /// If the issuer has a response cache, a retry of an identical request
+    /// gets the cached response.
+    pub fn purchase<R: RngCore + CryptoRng>(
+        &self,
+        wallet_secret: &wallet::Secrets,
+        token_secret: &Secrets,
+        issuer: &mut IssuerContext<impl Clock, impl NullifierStore>,
+        transcript: Transcript,
+        rng: R,
+    ) -> Result<Response, Error> {
+        issuer.respond(
+            b"token::purchase",
+            CredentialType::Wallet,
+            wallet_secret.cached_params.epoch,
+            self.n.to_bytes(),
+            &self.to_bytes(),
+            |issuer| self.process_purchase(wallet_secret, token_secret, issuer, transcript, rng),
+        )
+    }
+
+    #[allow(non_snake_case)]
+    fn process_purchase<R: RngCore + CryptoRng>(
+        &self,
+        wallet_secret: &wallet::Secrets,
+        token_secret: &Secrets,
+        issuer: &mut IssuerContext<impl Clock, impl NullifierStore>,
+        mut transcript: Transcript,
+        mut rng: R,
+    ) -> Result<Response, Error> {
+        let deployment = issuer.deployment;
+        deployment.check_version(self.version)?;
+
+        let params = &wallet_secret.cached_params;
+        let token_params = &token_secret.cached_params;
+        deployment.check_wallet_parameters(params)?;
+        deployment.check_token_parameters(token_params)?;
+
+        if params.epoch != self.epoch || token_params.epoch != self.token_epoch {
+            return Err(Error::WrongEpoch);
+        }
+        let now = issuer.clock.now();
+        self.epoch.check_presentable(now)?;
+        match self.token_epoch.state_at(now) {
+            EpochState::Active => {}
+            EpochState::Primary => {}
+            _ => return Err(Error::NewEpochState),
+        }
+
+        if !token_params.in_range(self.t) {
+            return Err(Error::ValueOutOfRange);
+        }
+
+        // The nullifier is reserved until the response is ready, and released
+        // if verification fails before then.
+        let reservation = Reservation::new(
+            issuer.nullifiers,
+            CredentialType::Wallet,
+            self.epoch,
+            self.n.to_bytes(),
+        )?
+        .ok_or(Error::NullifierReuse(CredentialType::Wallet))?;
+
+        let key = wallet_secret.key();
+        let V = self.presentation.presentation_point(&key, &self.n)?;
+        self.verify_proofs(deployment, params, token_params, &V, &mut transcript)?;
+
+        // The new wallet is reissued as in a debit, and the token is issued
+        // with the public balance t and the blinded nullifier n_t.
+        let reissue = self.presentation.reissue(&key, &mut transcript, &mut rng)?;
+        let issuance = self.presentation.issue(
+            &self.n_t,
+            &token_secret.key(),
+            self.t,
+            &mut transcript,
+            &mut rng,
+        )?;
+
+        reservation.commit()?;
+
+        Ok(Response {
+            version: self.version,
+            reissue,
+            issuance,
+        })
+    }
+}
+
+impl AwaitingResponse {
+    /// The request this state is awaiting a response to, which can be sent
+    /// again after a restart.
+    pub fn request(&self) -> &Request {
+        &self.state.request
+    }
+
+    /// Encode this state for storage while the request is in flight.
+    ///
+    /// The encoding contains the client's secrets for the new credential, so
+    /// it should be stored encrypted.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encoding::to_bytes(&self.state)
+    }
+
+    /// Restore a state encoded with [`AwaitingResponse::to_bytes`].
+    ///
+    /// The transcript is not stored, so it is rebuilt by replaying the
+    /// client's proofs on `transcript`, which must be constructed in the same
+    /// way as the transcript passed to [`Wallet::request_token_purchase`].
+    pub fn from_bytes(bytes: &[u8], mut transcript: Transcript) -> Result<Self, Error> {
+        let state: State = encoding::from_bytes(bytes)?;
+        state.request.verify_proofs(
+            &state.deployment,
+            &state.wallet_parameters,
+            &state.token_parameters,
+            state.opening.presentation_point(),
+            &mut transcript,
+        )?;
+        Ok(AwaitingResponse { transcript, state })
+    }
+
+    /// Verify a token purchase response and obtain the new wallet and token
+    /// credentials.
+    pub fn verify_response(mut self, response: Response) -> Result<(Wallet, Token), Error> {
+        if response.version != self.state.request.version {
+            return Err(Error::ProtocolVersion(response.version));
+        }
+
+        let request = &self.state.request;
+        let tag = self.state.opening.verify_reissue(
+            &request.presentation,
+            &response.reissue,
+            &self.state.wallet_parameters.public_key(),
+            &mut self.transcript,
+        )?;
+        let token_tag = self.state.opening.verify_issuance(
+            &request.n_t,
+            &response.issuance,
+            request.t,
+            &self.state.token_parameters.public_key(),
+            &mut self.transcript,
+        )?;
+
+        Ok((
+            Wallet {
+                epoch: self.state.wallet_parameters.epoch,
+                tag,
+                n: self.state.opening.n_prime(),
+                w: self.state.w_prime,
+            },
+            Token {
+                epoch: self.state.token_parameters.epoch,
+                tag: token_tag,
+                n: self.state.n_t,
+                t: request.t,
+            },
+        ))
+    }
+}
@@ -0,0 +1,44 @@
+use merlin::Transcript;
+use rand_core::{CryptoRng, RngCore};
+
+use crate::{rollover, DeploymentId, Error};
+
+use super::keys::Parameters;
+use super::Token;
+
+/// A request for token rollover.
+///
+/// The epoch states are computed using the token epoch schedule, so the
+/// issuer must process it with token secrets for the epochs named in the
+/// request.
+pub type Request = rollover::Request<Token>;
+
+/// State held by the client while awaiting a token rollover response.
+pub type AwaitingResponse = rollover::AwaitingResponse<Token>;
+
+/// A response to a token rollover request.
+pub type Response = rollover::Response<Token>;
+
+impl Token {
+    /// Request a rollover of this token from `old_parameters` to
+    /// `new_parameters`, consuming this credential and generating a rollover
+    /// request message together with the client state needed to verify a
+    /// response with a new token credential with the same balance.
+    pub fn request_rollover<R: RngCore + CryptoRng>(
+        self,
+        old_parameters: &Parameters,
+        new_parameters: &Parameters,
+        deployment: &DeploymentId,
+        transcript: Transcript,
+        rng: R,
+    ) -> Result<(AwaitingResponse, Request), Error> {
+        rollover::request(
+            self,
+            old_parameters,
+            new_parameters,
+            deployment,
+            transcript,
+            rng,
+        )
+    }
+}
@@ -0,0 +1,60 @@
+use merlin::Transcript;
+use rand_core::{CryptoRng, RngCore};
+
+use crate::presentation::{self, Protocol, Spend};
+use crate::{Clock, DeploymentId, Error, IssuerContext, NullifierStore};
+
+use super::keys::{Parameters, Secrets};
+use super::Token;
+
+impl Protocol for Spend {
+    type Credential = Token;
+    const LABEL: &'static [u8] = b"token::spend";
+    const AMOUNT_LABEL: &'static [u8] = b"v";
+    const DEDUCTS: bool = true;
+}
+
+/// A request to spend value from a token.
+pub type Request = presentation::Request<Spend>;
+
+/// State held by the client while awaiting a spend response.
+pub type AwaitingResponse = presentation::AwaitingResponse<Spend>;
+
+/// A response to a spend request.
+pub type Response = presentation::Response<Spend>;
+
+impl Token {
+    /// Request to spend value `v`, consuming this credential and generating a
+    /// spend request message together with the client state needed to verify
+    /// a response with a new token credential with balance `t - v`.
+    pub fn request_spend<R: RngCore + CryptoRng>(
+        self,
+        v: u64,
+        parameters: &Parameters,
+        deployment: &DeploymentId,
+        transcript: Transcript,
+        rng: R,
+    ) -> Result<(AwaitingResponse, Request), Error> {
+        presentation::request(self, v, parameters, deployment, transcript, rng)
+    }
+}
+
+impl Secrets {
+    /// Process a spend request, presenting the client's token and issuing a
+    /// new token with the spent value deducted.
+    ///
+    /// This function is solely responsible for the spend itself and not for
+    /// application policy (e.g., checking that the price `v` is correct).
+    ///
+    /// If the issuer has a response cache, a retry of an identical request
+    /// gets the cached response.
+    pub fn spend<R: RngCore + CryptoRng>(
+        &self,
+        request: Request,
+        issuer: &mut IssuerContext<impl Clock, impl NullifierStore>,
+        transcript: Transcript,
+        rng: R,
+    ) -> Result<Response, Error> {
+        presentation::respond(self, request, issuer, transcript, rng)
+    }
+}
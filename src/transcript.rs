@@ -1,22 +1,79 @@
-use merlin::Transcript;
+//! Binding of public protocol inputs into Merlin transcripts.
+//!
+//! Every protocol binds its public inputs to the transcript before the
+//! client's proofs are created or verified, so that a proof made for one set
+//! of public inputs does not verify for any other.  Both parties append the
+//! same values in the same order:
+//!
+//! 1. the domain separator, naming the protocol version, the deployment,
+//!    and the protocol;
+//! 2. the issuer parameters of each credential involved, including their
+//!    epoch, with the presented credential's parameters first;
+//! 3. the revealed amount, if any;
+//! 4. the revealed nullifier, if any.
+//!
+//! The proofs themselves then append their own commitments.
+
 use curve25519_dalek::scalar::Scalar;
+use merlin::Transcript;
 
-use byteorder::LittleEndian;
+use crate::{token, wallet, DeploymentId, Epoch};
 
-pub trait TranscriptProtocol {
-    fn dom_sep(&mut self);
-    fn append_epoch(&mut self, epoch: Epoch);
+pub(crate) trait TranscriptProtocol {
+    /// Append the Danake domain separator, the protocol version, the
+    /// deployment, and the name of the protocol.
+    fn dom_sep(&mut self, deployment: &DeploymentId, version: u16, protocol: &'static [u8]);
+    /// Append an epoch, as its duration and index.
+    fn append_epoch(&mut self, label: &'static [u8], epoch: Epoch);
+    /// Append wallet issuer parameters, as their epoch and public key.
+    fn append_wallet_parameters(&mut self, label: &'static [u8], parameters: &wallet::Parameters);
+    /// Append token issuer parameters, as their epoch, public key, and
+    /// rangeproof size.
+    fn append_token_parameters(&mut self, label: &'static [u8], parameters: &token::Parameters);
+    /// Append a revealed amount.
+    fn append_amount(&mut self, label: &'static [u8], amount: u64);
+    /// Append a revealed nullifier.
+    fn append_nullifier(&mut self, label: &'static [u8], n: &Scalar);
 }
 
 impl TranscriptProtocol for Transcript {
-    fn dom_sep(&mut self) {
-        self.append_message(b"dom-sep", b"Danake v0.1");
+    fn dom_sep(&mut self, deployment: &DeploymentId, version: u16, protocol: &'static [u8]) {
+        self.append_message(b"dom-sep", b"Danake");
+        self.append_u64(b"protocol_version", u64::from(version));
+        self.append_message(b"deployment", deployment.name().as_bytes());
+        self.append_message(b"deployment_parameters", deployment.parameters_hash());
+        self.append_message(b"protocol", protocol);
     }
 
-    fn append_epoch(&mut self, epoch: Epoch) {
+    fn append_epoch(&mut self, label: &'static [u8], epoch: Epoch) {
         let mut bytes = [0u8; 16];
-        LittleEndian::write_u64(&mut bytes[00..08], epoch.params.0);
-        LittleEndian::write_u64(&mut bytes[08..16], epoch.index);
-        self.append_message(b"epoch", &bytes[..]);
+        bytes[0..8].copy_from_slice(&epoch.params.0.to_le_bytes());
+        bytes[8..16].copy_from_slice(&epoch.index.to_le_bytes());
+        self.append_message(label, &bytes[..]);
+    }
+
+    fn append_wallet_parameters(&mut self, label: &'static [u8], parameters: &wallet::Parameters) {
+        self.append_message(label, b"wallet");
+        self.append_epoch(b"epoch", parameters.epoch);
+        self.append_message(b"X_0", parameters.X_0.compress().as_bytes());
+        self.append_message(b"X_1", parameters.X_1.compress().as_bytes());
+        self.append_message(b"X_2", parameters.X_2.compress().as_bytes());
+    }
+
+    fn append_token_parameters(&mut self, label: &'static [u8], parameters: &token::Parameters) {
+        self.append_message(label, b"token");
+        self.append_epoch(b"epoch", parameters.epoch);
+        self.append_message(b"X_0", parameters.X_0.compress().as_bytes());
+        self.append_message(b"X_1", parameters.X_1.compress().as_bytes());
+        self.append_message(b"X_2", parameters.X_2.compress().as_bytes());
+        self.append_u64(b"range_proof_bits", parameters.range_proof_bits as u64);
+    }
+
+    fn append_amount(&mut self, label: &'static [u8], amount: u64) {
+        self.append_u64(label, amount);
     }
-}
\ No newline at end of file
+
+    fn append_nullifier(&mut self, label: &'static [u8], n: &Scalar) {
+        self.append_message(label, n.as_bytes());
+    }
+}
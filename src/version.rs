@@ -0,0 +1,7 @@
+/// The version of the Danake protocols implemented by this crate.
+///
+/// It is sent in every request and response and bound into every protocol
+/// transcript, and changes whenever a proof statement or the public inputs
+/// bound to a transcript change.  Clients and issuers on different versions
+/// then fail with a version error rather than a proof failure.
+pub const PROTOCOL_VERSION: u16 = 1;
@@ -1,18 +1,80 @@
+use std::fmt;
+
 use curve25519_dalek::scalar::Scalar;
+use zeroize::Zeroize;
 
-use crate::{Epoch, Tag};
+use crate::presentation::Credential;
+use crate::{CredentialType, Epoch, Tag};
 
 /// A wallet token.
 pub struct Wallet {
-    epoch: Epoch,
-    w: u64,
-    n: Scalar,
-    tag: Tag,
+    pub(crate) epoch: Epoch,
+    pub(crate) w: u64,
+    pub(crate) n: Scalar,
+    pub(crate) tag: Tag,
+}
+
+impl Wallet {
+    /// The epoch of the parameters this wallet was issued under.
+    pub fn epoch(&self) -> Epoch {
+        self.epoch
+    }
+
+    /// The balance of this wallet.
+    pub fn balance(&self) -> u64 {
+        self.w
+    }
+}
+
+impl Credential for Wallet {
+    type Parameters = Parameters;
+    type Secrets = Secrets;
+    const TYPE: CredentialType = CredentialType::Wallet;
+    const ROLLOVER_LABEL: &'static [u8] = b"wallet::rollover";
+
+    fn epoch(&self) -> Epoch {
+        self.epoch
+    }
+
+    fn balance(&self) -> u64 {
+        self.w
+    }
+
+    fn nullifier(&self) -> &Scalar {
+        &self.n
+    }
+
+    fn tag(&self) -> &Tag {
+        &self.tag
+    }
+
+    fn reissued(epoch: Epoch, w: u64, n: Scalar, tag: Tag) -> Self {
+        Wallet { epoch, w, n, tag }
+    }
+}
+
+impl Drop for Wallet {
+    fn drop(&mut self) {
+        self.w.zeroize();
+        self.n.zeroize();
+        self.tag.zeroize();
+    }
+}
+
+impl fmt::Debug for Wallet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Wallet {{ epoch: {:?}, .. }}", self.epoch)
+    }
 }
 
 mod keys;
 pub use keys::{Parameters, Secrets};
 
+mod schedule;
+pub use schedule::{Decision, ParameterSchedule};
+
+mod storage;
+
 /// Issuance protocol states and messages.
 pub mod issuance;
 
@@ -21,3 +83,6 @@ pub mod topup;
 
 /// Rollover protocol states and messages.
 pub mod rollover;
+
+/// Debit protocol states and messages.
+pub mod debit;
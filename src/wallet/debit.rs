@@ -0,0 +1,61 @@
+use merlin::Transcript;
+use rand_core::{CryptoRng, RngCore};
+
+use crate::presentation::{self, Debit, Protocol};
+use crate::{Clock, DeploymentId, Error, IssuerContext, NullifierStore};
+
+use super::keys::{Parameters, Secrets};
+use super::Wallet;
+
+impl Protocol for Debit {
+    type Credential = Wallet;
+    const LABEL: &'static [u8] = b"wallet::debit";
+    const AMOUNT_LABEL: &'static [u8] = b"c";
+    const DEDUCTS: bool = true;
+}
+
+/// A request for a wallet debit.
+pub type Request = presentation::Request<Debit>;
+
+/// State held by the client while awaiting a debit response.
+pub type AwaitingResponse = presentation::AwaitingResponse<Debit>;
+
+/// A response to a debit request.
+pub type Response = presentation::Response<Debit>;
+
+impl Wallet {
+    /// Request a debit of `c` directly from this wallet, consuming this
+    /// credential and generating a debit request message together with the
+    /// client state needed to verify a response with a new wallet credential
+    /// with balance `w - c`.
+    pub fn request_debit<R: RngCore + CryptoRng>(
+        self,
+        c: u64,
+        parameters: &Parameters,
+        deployment: &DeploymentId,
+        transcript: Transcript,
+        rng: R,
+    ) -> Result<(AwaitingResponse, Request), Error> {
+        presentation::request(self, c, parameters, deployment, transcript, rng)
+    }
+}
+
+impl Secrets {
+    /// Process a debit request, presenting the client's wallet and issuing a
+    /// new wallet with the revealed amount `c` deducted.
+    ///
+    /// This function is solely responsible for the debit itself and not for
+    /// application policy (e.g., checking that the amount is correct).
+    ///
+    /// If the issuer has a response cache, a retry of an identical request
+    /// gets the cached response.
+    pub fn debit<R: RngCore + CryptoRng>(
+        &self,
+        request: Request,
+        issuer: &mut IssuerContext<impl Clock, impl NullifierStore>,
+        transcript: Transcript,
+        rng: R,
+    ) -> Result<Response, Error> {
+        presentation::respond(self, request, issuer, transcript, rng)
+    }
+}
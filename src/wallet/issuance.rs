@@ -1,3 +1,5 @@
+use std::fmt;
+
 use curve25519_dalek::{
     ristretto::{CompressedRistretto, RistrettoPoint},
     scalar::Scalar,
@@ -5,8 +7,13 @@ use curve25519_dalek::{
 };
 use merlin::Transcript;
 use rand_core::{CryptoRng, RngCore};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroize;
 
-use crate::{constants, Epoch, Tag};
+use crate::{
+    constants, encoding, DeploymentId, Epoch, Error, IssuerContext, Tag, TranscriptProtocol,
+    PROTOCOL_VERSION,
+};
 
 use super::keys::{Parameters, Secrets};
 use super::Wallet;
@@ -43,9 +50,10 @@ mod proofs {
 }
 
 /// A request for issuance of a wallet credential.
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 #[allow(non_snake_case)]
 pub struct Request {
+    version: u16,
     w: u64,
     epoch: Epoch,
     D: CompressedRistretto,
@@ -53,16 +61,80 @@ pub struct Request {
     proof: proofs::client::CompactProof,
 }
 
+impl_wire_format!(Request);
+
+/// Bind the public inputs of an issuance to `transcript`: the issuer
+/// parameters, then the requested amount `w`.
+fn append_public_inputs(
+    transcript: &mut Transcript,
+    deployment: &DeploymentId,
+    version: u16,
+    parameters: &Parameters,
+    w: u64,
+) {
+    transcript.dom_sep(deployment, version, b"wallet::issuance");
+    transcript.append_wallet_parameters(b"parameters", parameters);
+    transcript.append_amount(b"w", w);
+}
+
+impl Request {
+    /// Verify the client's proof, leaving `transcript` in the state the
+    /// client left it in after proving.
+    fn verify_proofs(
+        &self,
+        deployment: &DeploymentId,
+        parameters: &Parameters,
+        transcript: &mut Transcript,
+    ) -> Result<(), Error> {
+        append_public_inputs(transcript, deployment, self.version, parameters, self.w);
+        proofs::client::verify_compact(
+            &self.proof,
+            transcript,
+            proofs::client::VerifyAssignments {
+                D: &self.D,
+                Enc_nB_0: &self.Enc_nB.0,
+                Enc_nB_1: &self.Enc_nB.1,
+                B: &constants::B_COMPRESSED,
+            },
+        )
+        .map_err(|_| Error::ClientProof)
+    }
+}
+
 /// State held by the client while awaiting an issuance response.
 #[allow(non_snake_case)]
 pub struct AwaitingResponse {
-    parameters: Parameters,
     transcript: Transcript,
+    state: State,
+}
+
+impl fmt::Debug for AwaitingResponse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "AwaitingResponse(..)")
+    }
+}
+
+/// The part of an [`AwaitingResponse`] which is stored by
+/// [`AwaitingResponse::to_bytes`].
+#[derive(Clone, Serialize, Deserialize)]
+#[allow(non_snake_case)]
+struct State {
+    deployment: DeploymentId,
+    parameters: Parameters,
     w: u64,
     n: Scalar,
     d: Scalar,
     D: RistrettoPoint,
     Enc_nB: (CompressedRistretto, CompressedRistretto),
+    request: Request,
+}
+
+impl Drop for State {
+    fn drop(&mut self) {
+        self.w.zeroize();
+        self.n.zeroize();
+        self.d.zeroize();
+    }
 }
 
 impl Wallet {
@@ -73,6 +145,7 @@ impl Wallet {
     pub fn request_issuance<R: RngCore + CryptoRng>(
         w: u64,
         parameters: &Parameters,
+        deployment: &DeploymentId,
         mut transcript: Transcript,
         mut rng: R,
     ) -> (AwaitingResponse, Request) {
@@ -85,6 +158,8 @@ impl Wallet {
         let D = &d * B;
         let Enc_nB = (B * r, B * (n + r * d));
 
+        append_public_inputs(&mut transcript, deployment, PROTOCOL_VERSION, parameters, w);
+
         use proofs::client::*;
 
         // XXX zkp API should take an RNG
@@ -101,38 +176,48 @@ impl Wallet {
             },
         );
 
+        let request = Request {
+            version: PROTOCOL_VERSION,
+            w,
+            epoch: parameters.epoch,
+            D: points.D,
+            Enc_nB: (points.Enc_nB_0, points.Enc_nB_1),
+            proof,
+        };
+
         (
             AwaitingResponse {
-                // XXX avoid this clone
-                parameters: parameters.clone(),
                 transcript,
-                w,
-                n,
-                d,
-                D,
-                Enc_nB: (points.Enc_nB_0, points.Enc_nB_1),
-            },
-            Request {
-                w,
-                epoch: parameters.epoch,
-                D: points.D,
-                Enc_nB: (points.Enc_nB_0, points.Enc_nB_1),
-                proof,
+                state: State {
+                    deployment: deployment.clone(),
+                    // XXX avoid this clone
+                    parameters: parameters.clone(),
+                    w,
+                    n,
+                    d,
+                    D,
+                    Enc_nB: (points.Enc_nB_0, points.Enc_nB_1),
+                    request: request.clone(),
+                },
             },
+            request,
         )
     }
 }
 
 /// A response to a wallet issuance request.
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 #[allow(non_snake_case)]
 pub struct Response {
+    version: u16,
     P: CompressedRistretto,
     Enc_Q: (CompressedRistretto, CompressedRistretto),
     T_2: CompressedRistretto,
     proof: proofs::issuer::CompactProof,
 }
 
+impl_wire_format!(Response);
+
 impl Secrets {
     /// Issues a wallet credential in response to an issuance request.
     ///
@@ -140,42 +225,39 @@ impl Secrets {
     /// application policy (e.g., checking that the requested amount is valid).
     ///
     /// The response should be returned to the client, who can process it.
+    /// Issuance reveals no nullifier, so only the deployment of `issuer` is
+    /// used.
     #[allow(non_snake_case)]
-    pub fn issue<R: RngCore + CryptoRng>(
+    pub fn issue<C, N, R: RngCore + CryptoRng>(
         &self,
         request: Request,
+        issuer: &IssuerContext<C, N>,
         mut transcript: Transcript,
         mut rng: R,
-    ) -> Result<Response, &'static str> {
+    ) -> Result<Response, Error> {
+        let deployment = issuer.deployment;
+        deployment.check_version(request.version)?;
+
         let B: &RistrettoPoint = &constants::B;
 
         let sk = &self.inner;
         let params = &self.cached_params;
+        deployment.check_wallet_parameters(params)?;
 
         if request.epoch != params.epoch {
-            return Err("IssuanceRequest has wrong epoch for this IssuanceSecret");
+            return Err(Error::WrongEpoch);
         }
 
-        proofs::client::verify_compact(
-            &request.proof,
-            &mut transcript,
-            proofs::client::VerifyAssignments {
-                D: &request.D,
-                Enc_nB_0: &request.Enc_nB.0,
-                Enc_nB_1: &request.Enc_nB.1,
-                B: &constants::B_COMPRESSED,
-            },
-        )
-        .map_err(|_| "client proof failed to verify")?;
+        request.verify_proofs(deployment, params, &mut transcript)?;
 
         let b = Scalar::random(&mut rng);
         let r = Scalar::random(&mut rng);
 
         let Enc_nB = (
-            request.Enc_nB.0.decompress().ok_or("bad point")?,
-            request.Enc_nB.1.decompress().ok_or("bad point")?,
+            request.Enc_nB.0.decompress().ok_or(Error::Decompression)?,
+            request.Enc_nB.1.decompress().ok_or(Error::Decompression)?,
         );
-        let D = request.D.decompress().ok_or("failed to decompress")?;
+        let D = request.D.decompress().ok_or(Error::Decompression)?;
         let w = Scalar::from(request.w);
         let P = B * &b;
         let wP = B * &(b * w);
@@ -221,6 +303,7 @@ impl Secrets {
         );
 
         Ok(Response {
+            version: request.version,
             P: points.P,
             T_2: points.T_2_a,
             Enc_Q: (points.Enc_Q_0, points.Enc_Q_1),
@@ -230,12 +313,43 @@ impl Secrets {
 }
 
 impl AwaitingResponse {
+    /// The request this state is awaiting a response to, which can be sent
+    /// again after a restart.
+    pub fn request(&self) -> &Request {
+        &self.state.request
+    }
+
+    /// Encode this state for storage while the request is in flight.
+    ///
+    /// The encoding contains the client's secrets for the new credential, so
+    /// it should be stored encrypted.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encoding::to_bytes(&self.state)
+    }
+
+    /// Restore a state encoded with [`AwaitingResponse::to_bytes`].
+    ///
+    /// The transcript is not stored, so it is rebuilt by replaying the
+    /// client's proofs on `transcript`, which must be constructed in the same
+    /// way as the transcript passed to [`Wallet::request_issuance`].
+    pub fn from_bytes(bytes: &[u8], mut transcript: Transcript) -> Result<Self, Error> {
+        let state: State = encoding::from_bytes(bytes)?;
+        state
+            .request
+            .verify_proofs(&state.deployment, &state.parameters, &mut transcript)?;
+        Ok(AwaitingResponse { transcript, state })
+    }
+
     /// Verify an issuance response and obtain a wallet credential.
     #[allow(non_snake_case)]
-    pub fn verify_response(mut self, response: Response) -> Result<Wallet, &'static str> {
+    pub fn verify_response(mut self, response: Response) -> Result<Wallet, Error> {
+        if response.version != self.state.request.version {
+            return Err(Error::ProtocolVersion(response.version));
+        }
+
         // XXX-zkp: need to be able to pass either compressed or decompressed points or both
-        let P = response.P.decompress().ok_or("failed to decompress")?;
-        let wP = P * Scalar::from(self.w);
+        let P = response.P.decompress().ok_or(Error::Decompression)?;
+        let wP = P * Scalar::from(self.state.w);
 
         use proofs::issuer::*;
         verify_compact(
@@ -244,34 +358,34 @@ impl AwaitingResponse {
             VerifyAssignments {
                 P: &response.P,
                 wP: &wP.compress(),
-                D: &self.D.compress(),
-                Enc_nB_0: &self.Enc_nB.0,
-                Enc_nB_1: &self.Enc_nB.1,
+                D: &self.state.D.compress(),
+                Enc_nB_0: &self.state.Enc_nB.0,
+                Enc_nB_1: &self.state.Enc_nB.1,
                 Enc_Q_0: &response.Enc_Q.0,
                 Enc_Q_1: &response.Enc_Q.1,
                 T_2_a: &response.T_2,
                 T_2_b: &response.T_2,
-                X_0: &self.parameters.X_0.compress(),
-                X_1: &self.parameters.X_1.compress(),
-                X_2: &self.parameters.X_2.compress(),
+                X_0: &self.state.parameters.X_0.compress(),
+                X_1: &self.state.parameters.X_1.compress(),
+                X_2: &self.state.parameters.X_2.compress(),
                 B: &constants::B_COMPRESSED,
                 B_blinding: &constants::B_BLINDING_COMPRESSED,
             },
         )
-        .map_err(|_| "issuer proof failed to verify")?;
+        .map_err(|_| Error::IssuerProof)?;
 
         let Enc_Q = (
-            response.Enc_Q.0.decompress().ok_or("bad point")?,
-            response.Enc_Q.1.decompress().ok_or("bad point")?,
+            response.Enc_Q.0.decompress().ok_or(Error::Decompression)?,
+            response.Enc_Q.1.decompress().ok_or(Error::Decompression)?,
         );
 
-        let Q = Enc_Q.1 - self.d * Enc_Q.0;
+        let Q = Enc_Q.1 - self.state.d * Enc_Q.0;
 
         Ok(Wallet {
-            epoch: self.parameters.epoch,
+            epoch: self.state.parameters.epoch,
             tag: Tag { P, Q },
-            n: self.n,
-            w: self.w,
+            n: self.state.n,
+            w: self.state.w,
         })
     }
 }
@@ -1,42 +1,64 @@
+use std::fmt;
+
 use bulletproofs::PedersenGens;
 
 use curve25519_dalek::ristretto::RistrettoPoint;
 use curve25519_dalek::scalar::Scalar;
 
+use merlin::Transcript;
 use rand_core::{CryptoRng, RngCore};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroize;
 
-use crate::Epoch;
+use crate::keyfile::{self, KeyFileKey};
+use crate::presentation::{CredentialParameters, CredentialSecrets, Key, PublicKey};
+use crate::{CredentialType, DeploymentId, Epoch, Error, MasterSeed, TranscriptProtocol};
 
 /// Public parameters for a wallet issuer for a particular epoch.
 ///
 /// These are used by the client to prepare presentation proofs and to ensure
 /// that the client is using the same parameters as all otheer clients,
 /// preventing key partitioning attacks.
-#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
 #[allow(non_snake_case)]
 pub struct Parameters {
-    pub(super) X_0: RistrettoPoint,
-    pub(super) X_1: RistrettoPoint,
-    pub(super) X_2: RistrettoPoint,
-    pub(super) epoch: Epoch,
+    pub(crate) X_0: RistrettoPoint,
+    pub(crate) X_1: RistrettoPoint,
+    pub(crate) X_2: RistrettoPoint,
+    pub(crate) epoch: Epoch,
 }
 
-#[derive(Copy, Clone, Eq, PartialEq, Debug)]
-pub(super) struct Inner {
-    pub(super) x_0: Scalar,
-    pub(super) x_1: Scalar,
-    pub(super) x_2: Scalar,
-    pub(super) x_0_blinding: Scalar,
-    pub(super) epoch: Epoch,
+#[derive(Clone)]
+pub(crate) struct Inner {
+    pub(crate) x_0: Scalar,
+    pub(crate) x_1: Scalar,
+    pub(crate) x_2: Scalar,
+    pub(crate) x_0_blinding: Scalar,
+    pub(crate) epoch: Epoch,
 }
 
 /// Secret key material for a wallet issuer.
 ///
 /// Held by the issuer and used to issue and verify credentials.
-#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[derive(Clone)]
 pub struct Secrets {
-    pub(super) inner: Inner,
-    pub(super) cached_params: Parameters,
+    pub(crate) inner: Inner,
+    pub(crate) cached_params: Parameters,
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        self.x_0.zeroize();
+        self.x_1.zeroize();
+        self.x_2.zeroize();
+        self.x_0_blinding.zeroize();
+    }
+}
+
+impl fmt::Debug for Secrets {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Secrets {{ epoch: {:?}, .. }}", self.inner.epoch)
+    }
 }
 
 impl Inner {
@@ -51,9 +73,15 @@ impl Inner {
     }
 }
 
+impl Parameters {
+    /// The epoch these parameters are for.
+    pub fn epoch(&self) -> Epoch {
+        self.epoch
+    }
+}
+
 impl Secrets {
     pub fn new<R: RngCore + CryptoRng>(epoch: Epoch, mut rng: R) -> Secrets {
-        // XXX expand from a seed?
         let inner = Inner {
             epoch,
             x_0: Scalar::random(&mut rng),
@@ -62,10 +90,52 @@ impl Secrets {
             x_0_blinding: Scalar::random(&mut rng),
         };
         Secrets {
+            cached_params: inner.parameters(),
             inner,
+        }
+    }
+
+    /// Derive the wallet issuer secrets for `epoch` from `seed`.
+    pub fn derive(seed: &MasterSeed, epoch: Epoch) -> Secrets {
+        Secrets::from_scalars(epoch, seed.derive_scalars(CredentialType::Wallet, epoch))
+    }
+
+    /// Export these secrets as a key file encrypted under `key`, together
+    /// with their epoch and public parameters.
+    pub fn to_key_file<R: RngCore + CryptoRng>(&self, key: KeyFileKey, rng: R) -> Vec<u8> {
+        keyfile::export_secrets(self, key, rng)
+    }
+
+    /// Import secrets from a key file written by [`Secrets::to_key_file`].
+    ///
+    /// Fails with [`Error::ParametersMismatch`] if the public parameters
+    /// stored in the file are not those of the stored secrets.
+    pub fn from_key_file(file: &[u8], key: KeyFileKey) -> Result<Secrets, Error> {
+        keyfile::import_secrets(file, key)
+    }
+
+    /// Build secrets for `epoch` from the scalars `x_0`, `x_1`, `x_2`, and
+    /// `x_0_blinding`.
+    pub(crate) fn from_scalars(epoch: Epoch, scalars: [Scalar; 4]) -> Secrets {
+        let [x_0, x_1, x_2, x_0_blinding] = scalars;
+        let inner = Inner {
+            epoch,
+            x_0,
+            x_1,
+            x_2,
+            x_0_blinding,
+        };
+        Secrets {
             cached_params: inner.parameters(),
+            inner,
         }
     }
+
+    /// The scalars `x_0`, `x_1`, `x_2`, and `x_0_blinding`.
+    pub(crate) fn scalars(&self) -> [Scalar; 4] {
+        let inner = &self.inner;
+        [inner.x_0, inner.x_1, inner.x_2, inner.x_0_blinding]
+    }
 }
 
 impl<'a> From<&'a Secrets> for Parameters {
@@ -73,3 +143,47 @@ impl<'a> From<&'a Secrets> for Parameters {
         secret.cached_params.clone()
     }
 }
+
+impl CredentialParameters for Parameters {
+    fn epoch(&self) -> Epoch {
+        self.epoch
+    }
+
+    fn public_key(&self) -> PublicKey<'_> {
+        PublicKey {
+            X_0: &self.X_0,
+            X_1: &self.X_1,
+            X_2: &self.X_2,
+        }
+    }
+
+    fn range_proof_bits(&self) -> usize {
+        64
+    }
+
+    fn append_to_transcript(&self, label: &'static [u8], transcript: &mut Transcript) {
+        transcript.append_wallet_parameters(label, self);
+    }
+
+    fn check_deployment(&self, deployment: &DeploymentId) -> Result<(), Error> {
+        deployment.check_wallet_parameters(self)
+    }
+}
+
+impl CredentialSecrets for Secrets {
+    type Parameters = Parameters;
+
+    fn parameters(&self) -> &Parameters {
+        &self.cached_params
+    }
+
+    fn key(&self) -> Key<'_> {
+        Key {
+            x_0: &self.inner.x_0,
+            x_1: &self.inner.x_1,
+            x_2: &self.inner.x_2,
+            x_0_blinding: &self.inner.x_0_blinding,
+            public: self.cached_params.public_key(),
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+
+use crate::{Epoch, EpochParameters, Error, RolloverPolicy};
+
+use super::{Parameters, Wallet};
+
+/// What a client should do with a wallet before using it, as decided by
+/// [`ParameterSchedule::decide`].
+#[derive(Copy, Clone, Debug)]
+pub enum Decision<'a> {
+    /// The wallet's parameters are Primary (or will be next), so the wallet
+    /// can be used as-is with these parameters.
+    Use(&'a Parameters),
+    /// The wallet's parameters are no longer Primary, so the wallet should
+    /// be rolled over from `old` to `new` before it is used.
+    RollOver {
+        old: &'a Parameters,
+        new: &'a Parameters,
+    },
+    /// The wallet is too old to be rolled over under the issuer's rollover
+    /// policy, so it can no longer be used.
+    Expired,
+}
+
+/// The published wallet parameters known to a client, for the epochs around
+/// the current one.
+///
+/// Parameters are published while they are Active, before they become
+/// Primary, so a client can fetch them in advance.  Once a client knows the
+/// parameters for an epoch, it rejects different parameters for the same
+/// epoch, so an issuer cannot partition clients by giving them different
+/// keys.
+#[derive(Clone, Debug)]
+pub struct ParameterSchedule {
+    epoch_params: EpochParameters,
+    policy: RolloverPolicy,
+    parameters: BTreeMap<i64, Parameters>,
+}
+
+impl ParameterSchedule {
+    /// Create an empty schedule for epochs of the given duration.
+    pub fn new(epoch_params: EpochParameters) -> Self {
+        ParameterSchedule {
+            epoch_params,
+            policy: RolloverPolicy::default(),
+            parameters: BTreeMap::new(),
+        }
+    }
+
+    /// Keep parameters for as long as the issuer's `policy` accepts
+    /// rollovers, so that a wallet from an older epoch can still be rolled
+    /// over.
+    pub fn with_rollover_policy(mut self, policy: RolloverPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Add the published `parameters` for an epoch.
+    ///
+    /// Fails if the parameters use a different epoch duration, or if
+    /// different parameters are already known for their epoch.
+    pub fn insert(&mut self, parameters: Parameters) -> Result<(), Error> {
+        if parameters.epoch.params != self.epoch_params {
+            return Err(Error::WrongEpoch);
+        }
+        match self.parameters.get(&parameters.epoch.index) {
+            Some(known) if *known != parameters => Err(Error::ConflictingParameters),
+            Some(_) => Ok(()),
+            None => {
+                self.parameters.insert(parameters.epoch.index, parameters);
+                Ok(())
+            }
+        }
+    }
+
+    /// The parameters for `epoch`, if they are known.
+    pub fn get(&self, epoch: Epoch) -> Option<&Parameters> {
+        if epoch.params != self.epoch_params {
+            return None;
+        }
+        self.parameters.get(&epoch.index)
+    }
+
+    /// The Primary parameters at `time`, if they are known.
+    pub fn primary(&self, time: DateTime<Utc>) -> Option<&Parameters> {
+        self.get(self.epoch_params.epoch_at(time))
+    }
+
+    /// Drop the parameters of every epoch which has expired at `time`.
+    pub fn prune(&mut self, time: DateTime<Utc>) {
+        let policy = self.policy;
+        self.parameters
+            .retain(|_, parameters| !policy.has_expired_at(&parameters.epoch, time));
+    }
+
+    /// Decide whether `wallet` can be used as-is at `time`, must be rolled
+    /// over first, or has expired, as in step 1.1 of the topup protocol.
+    ///
+    /// A wallet from the epoch after the current one is used as-is, since
+    /// its parameters are Active and it cannot be rolled over backwards.
+    pub fn decide(&self, wallet: &Wallet, time: DateTime<Utc>) -> Result<Decision<'_>, Error> {
+        let epoch = wallet.epoch;
+        if epoch.params != self.epoch_params {
+            return Err(Error::WrongEpoch);
+        }
+        if self.policy.has_expired_at(&epoch, time) {
+            return Ok(Decision::Expired);
+        }
+
+        let current = self.epoch_params.epoch_at(time);
+        if epoch.index > current.index + 1 {
+            return Err(Error::InactiveEpoch);
+        }
+
+        let old = self.get(epoch).ok_or(Error::MissingParameters)?;
+        if epoch.index >= current.index {
+            return Ok(Decision::Use(old));
+        }
+        let new = self.get(current).ok_or(Error::MissingParameters)?;
+        Ok(Decision::RollOver { old, new })
+    }
+}
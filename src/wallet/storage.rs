@@ -0,0 +1,47 @@
+use curve25519_dalek::scalar::Scalar;
+use rand_core::{CryptoRng, RngCore};
+
+use crate::{container, encoding, Epoch, EpochParameters, Error, Tag};
+
+use super::Wallet;
+
+/// The label binding sealed containers to wallets.
+const SEALED_WALLET_LABEL: &[u8] = b"danake wallet";
+
+impl Wallet {
+    /// Encode this wallet in the versioned wire format.
+    ///
+    /// The encoding contains the wallet's nullifier and MAC tag in the
+    /// clear, so it should only be stored using [`Wallet::seal`].
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encoding::to_bytes(&(&self.epoch, self.w, &self.n, &self.tag))
+    }
+
+    /// Decode a wallet encoded with [`Wallet::to_bytes`], checking that its
+    /// epoch and tag are well-formed.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Wallet, Error> {
+        // The epoch is decoded field by field, so that a malformed duration
+        // is reported as a malformed credential rather than an encoding
+        // error.
+        let (index, duration, w, n, tag): (i64, u64, u64, Scalar, Tag) =
+            encoding::from_bytes(bytes)?;
+        let epoch = Epoch {
+            index,
+            params: EpochParameters(duration),
+        };
+        if !epoch.is_well_formed() || !tag.is_well_formed() {
+            return Err(Error::MalformedCredential);
+        }
+        Ok(Wallet { epoch, w, n, tag })
+    }
+
+    /// Encrypt this wallet under the user's storage `key`.
+    pub fn seal<R: RngCore + CryptoRng>(&self, key: &[u8; 32], rng: R) -> Vec<u8> {
+        container::seal(SEALED_WALLET_LABEL, &self.to_bytes(), key, rng)
+    }
+
+    /// Decrypt a wallet sealed with [`Wallet::seal`] under the same `key`.
+    pub fn unseal(sealed: &[u8], key: &[u8; 32]) -> Result<Wallet, Error> {
+        Wallet::from_bytes(&container::open(SEALED_WALLET_LABEL, sealed, key)?)
+    }
+}
@@ -2,6 +2,21 @@ use chrono;
 use merlin::Transcript;
 use rand;
 use danake;
+use danake::nullifier::MemoryNullifierStore;
+use danake::{IssuerContext, SystemClock};
+
+/// The deployment the tests run in.
+fn deployment() -> danake::DeploymentId {
+    let epoch_params = danake::EpochParameters::from(std::time::Duration::from_secs(86400));
+    danake::DeploymentId::new("test", epoch_params, epoch_params, 32)
+}
+
+/// A deployment with hour-long token epochs and 16-bit token rangeproofs.
+fn token_deployment() -> danake::DeploymentId {
+    let wallet_epoch_params = danake::EpochParameters::from(std::time::Duration::from_secs(86400));
+    let token_epoch_params = danake::EpochParameters::from(std::time::Duration::from_secs(3600));
+    danake::DeploymentId::new("test", wallet_epoch_params, token_epoch_params, 16)
+}
 
 #[test]
 fn wallet_issuance_topup_and_rollover() {
@@ -16,6 +31,7 @@ fn wallet_issuance_topup_and_rollover() {
     let (client_state, request) = Wallet::request_issuance(
         1_000,
         &params,
+        &deployment(),
         Transcript::new(b"wallet issuance test"),
         rand::thread_rng(),
     );
@@ -23,6 +39,11 @@ fn wallet_issuance_topup_and_rollover() {
     let response = secret
         .issue(
             request,
+            &mut IssuerContext::new(
+                &deployment(),
+                &SystemClock,
+                &mut MemoryNullifierStore::new(),
+            ),
             Transcript::new(b"wallet issuance test"),
             rand::thread_rng(),
         )
@@ -36,19 +57,35 @@ fn wallet_issuance_topup_and_rollover() {
         .request_topup(
             2_000,
             &params,
+            &deployment(),
             Transcript::new(b"wallet topup test"),
             rand::thread_rng(),
         )
         .expect("epoch is correct");
 
+    let mut nullifiers = MemoryNullifierStore::new();
+
     let response = secret
         .topup(
-            request,
+            request.clone(),
+            &mut IssuerContext::new(&deployment(), &SystemClock, &mut nullifiers),
             Transcript::new(b"wallet topup test"),
             rand::thread_rng(),
         )
         .expect("topup should succeed");
 
+    assert!(matches!(
+        secret.topup(
+            request,
+            &mut IssuerContext::new(&deployment(), &SystemClock, &mut nullifiers),
+            Transcript::new(b"wallet topup test"),
+            rand::thread_rng()
+        ),
+        Err(danake::Error::NullifierReuse(
+            danake::CredentialType::Wallet
+        ))
+    ));
+
     let wallet2 = client_state
         .verify_response(response)
         .expect("response should verify");
@@ -61,21 +98,19 @@ fn wallet_issuance_topup_and_rollover() {
     .request_rollover(
         &params,
         &new_parameters,
+        &deployment(),
         Transcript::new(b"wallet rollover test"),
         rand::thread_rng(),
     )
     .expect("rollover request should succeed");
 
-    let nullifier_result = true;
-    let check_and_update_closure = |_x| {nullifier_result};
-
     let response = request
         .rollover(
             &secret,
             &new_secret,
+            &mut IssuerContext::new(&deployment(), &SystemClock, &mut nullifiers),
             Transcript::new(b"wallet rollover test"),
             rand::thread_rng(),
-            check_and_update_closure,
         )
         .expect("rollover should succeed");
 
@@ -83,3 +118,2695 @@ fn wallet_issuance_topup_and_rollover() {
         .verify_response(response)
         .expect("response should verify");
 }
+
+#[test]
+fn token_purchase_and_spend() {
+    use danake::{token, wallet, EpochParameters};
+
+    let wallet_epoch_params = EpochParameters::from(std::time::Duration::from_secs(86400));
+    let token_epoch_params = EpochParameters::from(std::time::Duration::from_secs(3600));
+    let wallet_epoch = wallet_epoch_params.epoch_at(chrono::Utc::now());
+    let token_epoch = token_epoch_params.epoch_at(chrono::Utc::now());
+
+    let wallet_secret = wallet::Secrets::new(wallet_epoch, rand::thread_rng());
+    let wallet_params = wallet::Parameters::from(&wallet_secret);
+    let token_secret = token::Secrets::new(token_epoch, 16, rand::thread_rng());
+    let token_params = token::Parameters::from(&token_secret);
+
+    let (client_state, request) = wallet::Wallet::request_issuance(
+        1_000,
+        &wallet_params,
+        &token_deployment(),
+        Transcript::new(b"wallet issuance test"),
+        rand::thread_rng(),
+    );
+
+    let response = wallet_secret
+        .issue(
+            request,
+            &mut IssuerContext::new(
+                &token_deployment(),
+                &SystemClock,
+                &mut MemoryNullifierStore::new(),
+            ),
+            Transcript::new(b"wallet issuance test"),
+            rand::thread_rng(),
+        )
+        .expect("issuance should succeed");
+
+    let wallet = client_state
+        .verify_response(response)
+        .expect("response should verify");
+
+    let (client_state, request) = wallet
+        .request_token_purchase(
+            300,
+            &wallet_params,
+            &token_params,
+            &token_deployment(),
+            Transcript::new(b"token purchase test"),
+            rand::thread_rng(),
+        )
+        .expect("purchase request should succeed");
+
+    let mut nullifiers = MemoryNullifierStore::new();
+
+    let response = request
+        .purchase(
+            &wallet_secret,
+            &token_secret,
+            &mut IssuerContext::new(&token_deployment(), &SystemClock, &mut nullifiers),
+            Transcript::new(b"token purchase test"),
+            rand::thread_rng(),
+        )
+        .expect("purchase should succeed");
+
+    assert!(request
+        .purchase(
+            &wallet_secret,
+            &token_secret,
+            &mut IssuerContext::new(&token_deployment(), &SystemClock, &mut nullifiers),
+            Transcript::new(b"token purchase test"),
+            rand::thread_rng()
+        )
+        .is_err());
+
+    let (wallet2, token) = client_state
+        .verify_response(response)
+        .expect("response should verify");
+
+    assert!(wallet2
+        .request_token_purchase(
+            701,
+            &wallet_params,
+            &token_params,
+            &token_deployment(),
+            Transcript::new(b"token purchase test"),
+            rand::thread_rng(),
+        )
+        .is_err());
+
+    let (client_state, request) = token
+        .request_spend(
+            120,
+            &token_params,
+            &token_deployment(),
+            Transcript::new(b"token spend test"),
+            rand::thread_rng(),
+        )
+        .expect("spend request should succeed");
+
+    let response = token_secret
+        .spend(
+            request.clone(),
+            &mut IssuerContext::new(&token_deployment(), &SystemClock, &mut nullifiers),
+            Transcript::new(b"token spend test"),
+            rand::thread_rng(),
+        )
+        .expect("spend should succeed");
+
+    assert!(token_secret
+        .spend(
+            request,
+            &mut IssuerContext::new(&token_deployment(), &SystemClock, &mut nullifiers),
+            Transcript::new(b"token spend test"),
+            rand::thread_rng()
+        )
+        .is_err());
+
+    let token2 = client_state
+        .verify_response(response)
+        .expect("response should verify");
+
+    assert!(token2
+        .request_spend(
+            181,
+            &token_params,
+            &token_deployment(),
+            Transcript::new(b"token spend test"),
+            rand::thread_rng(),
+        )
+        .is_err());
+}
+
+#[test]
+fn token_rollover() {
+    use danake::{token, wallet, EpochParameters};
+
+    let now = chrono::Utc::now();
+    let wallet_epoch_params = EpochParameters::from(std::time::Duration::from_secs(86400));
+    let token_epoch_params = EpochParameters::from(std::time::Duration::from_secs(3600));
+    let wallet_epoch = wallet_epoch_params.epoch_at(now);
+    let token_epoch = token_epoch_params.epoch_at(now);
+
+    let wallet_secret = wallet::Secrets::new(wallet_epoch, rand::thread_rng());
+    let wallet_params = wallet::Parameters::from(&wallet_secret);
+    let token_secret = token::Secrets::new(token_epoch, 16, rand::thread_rng());
+    let token_params = token::Parameters::from(&token_secret);
+
+    let (client_state, request) = wallet::Wallet::request_issuance(
+        1_000,
+        &wallet_params,
+        &token_deployment(),
+        Transcript::new(b"wallet issuance test"),
+        rand::thread_rng(),
+    );
+
+    let response = wallet_secret
+        .issue(
+            request,
+            &mut IssuerContext::new(
+                &token_deployment(),
+                &SystemClock,
+                &mut MemoryNullifierStore::new(),
+            ),
+            Transcript::new(b"wallet issuance test"),
+            rand::thread_rng(),
+        )
+        .expect("issuance should succeed");
+
+    let wallet = client_state
+        .verify_response(response)
+        .expect("response should verify");
+
+    let (client_state, request) = wallet
+        .request_token_purchase(
+            300,
+            &wallet_params,
+            &token_params,
+            &token_deployment(),
+            Transcript::new(b"token purchase test"),
+            rand::thread_rng(),
+        )
+        .expect("purchase request should succeed");
+
+    let response = request
+        .purchase(
+            &wallet_secret,
+            &token_secret,
+            &mut IssuerContext::new(
+                &token_deployment(),
+                &SystemClock,
+                &mut MemoryNullifierStore::new(),
+            ),
+            Transcript::new(b"token purchase test"),
+            rand::thread_rng(),
+        )
+        .expect("purchase should succeed");
+
+    let (_wallet, token) = client_state
+        .verify_response(response)
+        .expect("response should verify");
+
+    let new_epoch = token_epoch_params.epoch_at(now + chrono::Duration::hours(1));
+    let new_secret = token::Secrets::new(new_epoch, 16, rand::thread_rng());
+    let new_params = token::Parameters::from(&new_secret);
+
+    let mut nullifiers = MemoryNullifierStore::new();
+
+    let (client_state, request) = token
+        .request_rollover(
+            &token_params,
+            &new_params,
+            &token_deployment(),
+            Transcript::new(b"token rollover test"),
+            rand::thread_rng(),
+        )
+        .expect("rollover request should succeed");
+
+    let response = request
+        .rollover(
+            &token_secret,
+            &new_secret,
+            &mut IssuerContext::new(&token_deployment(), &SystemClock, &mut nullifiers),
+            Transcript::new(b"token rollover test"),
+            rand::thread_rng(),
+        )
+        .expect("rollover should succeed");
+
+    assert!(request
+        .rollover(
+            &token_secret,
+            &new_secret,
+            &mut IssuerContext::new(&token_deployment(), &SystemClock, &mut nullifiers),
+            Transcript::new(b"token rollover test"),
+            rand::thread_rng()
+        )
+        .is_err());
+
+    let token2 = client_state
+        .verify_response(response)
+        .expect("response should verify");
+
+    // Two token epochs ahead is still within the wallet schedule, but it is
+    // not yet Active in the token schedule.
+    let far_epoch = token_epoch_params.epoch_at(now + chrono::Duration::hours(2));
+    let far_secret = token::Secrets::new(far_epoch, 16, rand::thread_rng());
+    let far_params = token::Parameters::from(&far_secret);
+
+    let (_client_state, request) = token2
+        .request_rollover(
+            &new_params,
+            &far_params,
+            &token_deployment(),
+            Transcript::new(b"token rollover test"),
+            rand::thread_rng(),
+        )
+        .expect("rollover request should succeed");
+
+    assert!(request
+        .rollover(
+            &new_secret,
+            &far_secret,
+            &mut IssuerContext::new(&token_deployment(), &SystemClock, &mut nullifiers),
+            Transcript::new(b"token rollover test"),
+            rand::thread_rng()
+        )
+        .is_err());
+}
+
+#[test]
+fn wallet_debit() {
+    use danake::{wallet::*, EpochParameters};
+
+    let epoch_params = EpochParameters::from(std::time::Duration::from_secs(86400));
+    let epoch = epoch_params.epoch_at(chrono::Utc::now());
+
+    let secret = Secrets::new(epoch, rand::thread_rng());
+    let params = Parameters::from(&secret);
+
+    let (client_state, request) = Wallet::request_issuance(
+        1_000,
+        &params,
+        &deployment(),
+        Transcript::new(b"wallet issuance test"),
+        rand::thread_rng(),
+    );
+
+    let response = secret
+        .issue(
+            request,
+            &mut IssuerContext::new(
+                &deployment(),
+                &SystemClock,
+                &mut MemoryNullifierStore::new(),
+            ),
+            Transcript::new(b"wallet issuance test"),
+            rand::thread_rng(),
+        )
+        .expect("issuance should succeed");
+
+    let wallet = client_state
+        .verify_response(response)
+        .expect("response should verify");
+
+    let mut nullifiers = MemoryNullifierStore::new();
+
+    let (client_state, request) = wallet
+        .request_debit(
+            400,
+            &params,
+            &deployment(),
+            Transcript::new(b"wallet debit test"),
+            rand::thread_rng(),
+        )
+        .expect("debit request should succeed");
+
+    let response = secret
+        .debit(
+            request.clone(),
+            &mut IssuerContext::new(&deployment(), &SystemClock, &mut nullifiers),
+            Transcript::new(b"wallet debit test"),
+            rand::thread_rng(),
+        )
+        .expect("debit should succeed");
+
+    assert!(secret
+        .debit(
+            request,
+            &mut IssuerContext::new(&deployment(), &SystemClock, &mut nullifiers),
+            Transcript::new(b"wallet debit test"),
+            rand::thread_rng()
+        )
+        .is_err());
+
+    let wallet2 = client_state
+        .verify_response(response)
+        .expect("response should verify");
+
+    assert!(wallet2
+        .request_debit(
+            601,
+            &params,
+            &deployment(),
+            Transcript::new(b"wallet debit test"),
+            rand::thread_rng(),
+        )
+        .is_err());
+}
+
+#[test]
+fn failed_presentation_does_not_spend_nullifier() {
+    use danake::{wallet::*, CredentialType, EpochParameters, NullifierStore};
+
+    let epoch_params = EpochParameters::from(std::time::Duration::from_secs(86400));
+    let epoch = epoch_params.epoch_at(chrono::Utc::now());
+
+    let secret = Secrets::new(epoch, rand::thread_rng());
+    let params = Parameters::from(&secret);
+
+    let (client_state, request) = Wallet::request_issuance(
+        1_000,
+        &params,
+        &deployment(),
+        Transcript::new(b"wallet issuance test"),
+        rand::thread_rng(),
+    );
+
+    let response = secret
+        .issue(
+            request,
+            &mut IssuerContext::new(
+                &deployment(),
+                &SystemClock,
+                &mut MemoryNullifierStore::new(),
+            ),
+            Transcript::new(b"wallet issuance test"),
+            rand::thread_rng(),
+        )
+        .expect("issuance should succeed");
+
+    let wallet = client_state
+        .verify_response(response)
+        .expect("response should verify");
+
+    let mut nullifiers = MemoryNullifierStore::new();
+
+    let (client_state, request) = wallet
+        .request_topup(
+            100,
+            &params,
+            &deployment(),
+            Transcript::new(b"wallet topup test"),
+            rand::thread_rng(),
+        )
+        .expect("topup request should succeed");
+
+    // Verification fails after the nullifier has been reserved, which must
+    // release it again.
+    assert!(matches!(
+        secret.topup(
+            request.clone(),
+            &mut IssuerContext::new(&deployment(), &SystemClock, &mut nullifiers),
+            Transcript::new(b"mismatched transcript"),
+            rand::thread_rng()
+        ),
+        Err(danake::Error::ClientProof)
+    ));
+
+    let response = secret
+        .topup(
+            request.clone(),
+            &mut IssuerContext::new(&deployment(), &SystemClock, &mut nullifiers),
+            Transcript::new(b"wallet topup test"),
+            rand::thread_rng(),
+        )
+        .expect("retried topup should succeed");
+
+    assert!(matches!(
+        secret.topup(
+            request,
+            &mut IssuerContext::new(&deployment(), &SystemClock, &mut nullifiers),
+            Transcript::new(b"wallet topup test"),
+            rand::thread_rng()
+        ),
+        Err(danake::Error::NullifierReuse(
+            danake::CredentialType::Wallet
+        ))
+    ));
+
+    client_state
+        .verify_response(response)
+        .expect("response should verify");
+
+    // A reserved nullifier cannot be reserved again until it is released.
+    let nullifier = [7u8; 32];
+    assert!(nullifiers
+        .reserve(CredentialType::Token, epoch, nullifier)
+        .unwrap());
+    assert!(!nullifiers
+        .reserve(CredentialType::Token, epoch, nullifier)
+        .unwrap());
+    nullifiers.release(CredentialType::Token, epoch, nullifier);
+    assert!(!nullifiers.contains(CredentialType::Token, epoch, &nullifier));
+    assert!(nullifiers
+        .reserve(CredentialType::Token, epoch, nullifier)
+        .unwrap());
+    nullifiers
+        .commit(CredentialType::Token, epoch, nullifier)
+        .unwrap();
+    assert!(nullifiers.contains(CredentialType::Token, epoch, &nullifier));
+    assert!(!nullifiers
+        .reserve(CredentialType::Token, epoch, nullifier)
+        .unwrap());
+}
+
+#[test]
+fn nullifier_sets_are_pruned_after_rollover() {
+    use danake::{CredentialType, EpochParameters, NullifierStore};
+
+    let now = chrono::Utc::now();
+    let epoch_params = EpochParameters::from(std::time::Duration::from_secs(86400));
+    let epoch = epoch_params.epoch_at(now);
+
+    let mut store = MemoryNullifierStore::new();
+
+    assert!(store
+        .check_and_insert(CredentialType::Wallet, epoch, [1; 32])
+        .unwrap());
+    assert!(!store
+        .check_and_insert(CredentialType::Wallet, epoch, [1; 32])
+        .unwrap());
+    // Each credential type has its own nullifier set.
+    assert!(store
+        .check_and_insert(CredentialType::Token, epoch, [1; 32])
+        .unwrap());
+
+    // The set is retained while the epoch is in Rollover state...
+    store.prune(now + chrono::Duration::days(2));
+    assert!(store.contains(CredentialType::Wallet, epoch, &[1; 32]));
+
+    // ...and dropped once the epoch expires.
+    store.prune(now + chrono::Duration::days(3));
+    assert!(!store.contains(CredentialType::Wallet, epoch, &[1; 32]));
+    assert!(!store.contains(CredentialType::Token, epoch, &[1; 32]));
+}
+
+#[test]
+fn file_nullifier_store_survives_kill_and_restart() {
+    use chrono::TimeZone;
+    use danake::nullifier::FileNullifierStore;
+    use danake::{CredentialType, EpochParameters, NullifierStore};
+    use std::io::Write;
+
+    const DIR_VAR: &str = "DANAKE_TEST_NULLIFIER_DIR";
+    const TIME_VAR: &str = "DANAKE_TEST_NULLIFIER_TIME";
+
+    let epoch_params = EpochParameters::from(std::time::Duration::from_secs(86400));
+
+    // In the child process, spend a nullifier and die without any cleanup.
+    if let Ok(dir) = std::env::var(DIR_VAR) {
+        let time: i64 = std::env::var(TIME_VAR).unwrap().parse().unwrap();
+        let epoch = epoch_params.epoch_at(chrono::Utc.timestamp(time, 0));
+        let mut store = FileNullifierStore::open(&dir).unwrap();
+        assert!(store
+            .check_and_insert(CredentialType::Wallet, epoch, [7; 32])
+            .unwrap());
+        std::process::abort();
+    }
+
+    let now = chrono::Utc::now();
+    let epoch = epoch_params.epoch_at(now);
+    let dir = std::env::temp_dir().join(format!("danake-nullifiers-{}", std::process::id()));
+
+    let status = std::process::Command::new(std::env::current_exe().unwrap())
+        .args(&["file_nullifier_store_survives_kill_and_restart", "--exact"])
+        .env(DIR_VAR, &dir)
+        .env(TIME_VAR, now.timestamp().to_string())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .unwrap();
+    assert!(!status.success(), "child process should have been killed");
+
+    // Simulate a torn write of a second nullifier when the process died.
+    let log = std::fs::read_dir(&dir)
+        .unwrap()
+        .next()
+        .expect("child should have created a log")
+        .unwrap()
+        .path();
+    std::fs::OpenOptions::new()
+        .append(true)
+        .open(&log)
+        .unwrap()
+        .write_all(&[8; 5])
+        .unwrap();
+
+    let mut store = FileNullifierStore::open(&dir).unwrap();
+    assert!(store.contains(CredentialType::Wallet, epoch, &[7; 32]));
+    assert!(!store
+        .check_and_insert(CredentialType::Wallet, epoch, [7; 32])
+        .unwrap());
+    assert!(store
+        .check_and_insert(CredentialType::Wallet, epoch, [8; 32])
+        .unwrap());
+    drop(store);
+
+    // Expired epochs have their logs deleted.
+    let mut store = FileNullifierStore::open(&dir).unwrap();
+    assert!(store.contains(CredentialType::Wallet, epoch, &[8; 32]));
+    store.prune(now + chrono::Duration::days(3)).unwrap();
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn file_nullifier_store_skips_malformed_logs() {
+    use danake::nullifier::FileNullifierStore;
+    use danake::{CredentialType, EpochParameters, NullifierStore};
+
+    let dir = std::env::temp_dir().join(format!("danake-malformed-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    // Neither an epoch duration of zero nor an extreme index crashes the
+    // store when it opens and prunes its logs.
+    for name in &["wallet_0_18000.log", "token_86400_-9223372036854775808.log"] {
+        std::fs::write(dir.join(name), &[7; 32]).unwrap();
+    }
+
+    let epoch_params = EpochParameters::from(std::time::Duration::from_secs(86400));
+    let epoch = epoch_params.epoch_at(chrono::Utc::now());
+    let mut store = FileNullifierStore::open(&dir).unwrap();
+    assert!(store
+        .check_and_insert(CredentialType::Wallet, epoch, [7; 32])
+        .unwrap());
+    store.prune(chrono::Utc::now()).unwrap();
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Send a message through its wire encoding, checking that the decoded
+/// message encodes to the same bytes.
+macro_rules! round_trip {
+    ($message:expr, $ty:ty) => {{
+        let bytes = $message.to_bytes();
+        let decoded = <$ty>::from_bytes(&bytes).expect("message should decode");
+        assert_eq!(decoded.to_bytes(), bytes);
+        decoded
+    }};
+}
+
+#[test]
+fn messages_round_trip_through_wire_format() {
+    use danake::{token, wallet, EpochParameters};
+
+    let now = chrono::Utc::now();
+    let wallet_epoch_params = EpochParameters::from(std::time::Duration::from_secs(86400));
+    let token_epoch_params = EpochParameters::from(std::time::Duration::from_secs(3600));
+    let wallet_epoch = wallet_epoch_params.epoch_at(now);
+    let token_epoch = token_epoch_params.epoch_at(now);
+
+    let wallet_secret = wallet::Secrets::new(wallet_epoch, rand::thread_rng());
+    let wallet_params = wallet::Parameters::from(&wallet_secret);
+    let token_secret = token::Secrets::new(token_epoch, 16, rand::thread_rng());
+    let token_params = token::Parameters::from(&token_secret);
+
+    let mut nullifiers = MemoryNullifierStore::new();
+
+    let (client_state, request) = wallet::Wallet::request_issuance(
+        1_000,
+        &wallet_params,
+        &token_deployment(),
+        Transcript::new(b"wallet issuance test"),
+        rand::thread_rng(),
+    );
+    let request = round_trip!(request, wallet::issuance::Request);
+    let response = wallet_secret
+        .issue(
+            request,
+            &mut IssuerContext::new(
+                &token_deployment(),
+                &SystemClock,
+                &mut MemoryNullifierStore::new(),
+            ),
+            Transcript::new(b"wallet issuance test"),
+            rand::thread_rng(),
+        )
+        .expect("issuance should succeed");
+    let response = round_trip!(response, wallet::issuance::Response);
+    let wallet = client_state
+        .verify_response(response)
+        .expect("response should verify");
+
+    let (client_state, request) = wallet
+        .request_topup(
+            500,
+            &wallet_params,
+            &token_deployment(),
+            Transcript::new(b"wallet topup test"),
+            rand::thread_rng(),
+        )
+        .expect("topup request should succeed");
+    let request = round_trip!(request, wallet::topup::Request);
+    let response = wallet_secret
+        .topup(
+            request,
+            &mut IssuerContext::new(&token_deployment(), &SystemClock, &mut nullifiers),
+            Transcript::new(b"wallet topup test"),
+            rand::thread_rng(),
+        )
+        .expect("topup should succeed");
+    let response = round_trip!(response, wallet::topup::Response);
+    let wallet = client_state
+        .verify_response(response)
+        .expect("response should verify");
+
+    let (client_state, request) = wallet
+        .request_debit(
+            200,
+            &wallet_params,
+            &token_deployment(),
+            Transcript::new(b"wallet debit test"),
+            rand::thread_rng(),
+        )
+        .expect("debit request should succeed");
+    let request = round_trip!(request, wallet::debit::Request);
+    let response = wallet_secret
+        .debit(
+            request,
+            &mut IssuerContext::new(&token_deployment(), &SystemClock, &mut nullifiers),
+            Transcript::new(b"wallet debit test"),
+            rand::thread_rng(),
+        )
+        .expect("debit should succeed");
+    let response = round_trip!(response, wallet::debit::Response);
+    let wallet = client_state
+        .verify_response(response)
+        .expect("response should verify");
+
+    let (client_state, request) = wallet
+        .request_token_purchase(
+            300,
+            &wallet_params,
+            &token_params,
+            &token_deployment(),
+            Transcript::new(b"token purchase test"),
+            rand::thread_rng(),
+        )
+        .expect("purchase request should succeed");
+    let request = round_trip!(request, token::purchase::Request);
+    let response = request
+        .purchase(
+            &wallet_secret,
+            &token_secret,
+            &mut IssuerContext::new(&token_deployment(), &SystemClock, &mut nullifiers),
+            Transcript::new(b"token purchase test"),
+            rand::thread_rng(),
+        )
+        .expect("purchase should succeed");
+    let response = round_trip!(response, token::purchase::Response);
+    let (wallet, token) = client_state
+        .verify_response(response)
+        .expect("response should verify");
+
+    let (client_state, request) = token
+        .request_spend(
+            100,
+            &token_params,
+            &token_deployment(),
+            Transcript::new(b"token spend test"),
+            rand::thread_rng(),
+        )
+        .expect("spend request should succeed");
+    let request = round_trip!(request, token::spend::Request);
+    let response = token_secret
+        .spend(
+            request,
+            &mut IssuerContext::new(&token_deployment(), &SystemClock, &mut nullifiers),
+            Transcript::new(b"token spend test"),
+            rand::thread_rng(),
+        )
+        .expect("spend should succeed");
+    let response = round_trip!(response, token::spend::Response);
+    let token = client_state
+        .verify_response(response)
+        .expect("response should verify");
+
+    let new_token_epoch = token_epoch_params.epoch_at(now + chrono::Duration::hours(1));
+    let new_token_secret = token::Secrets::new(new_token_epoch, 16, rand::thread_rng());
+    let new_token_params = token::Parameters::from(&new_token_secret);
+
+    let (client_state, request) = token
+        .request_rollover(
+            &token_params,
+            &new_token_params,
+            &token_deployment(),
+            Transcript::new(b"token rollover test"),
+            rand::thread_rng(),
+        )
+        .expect("rollover request should succeed");
+    let request = round_trip!(request, token::rollover::Request);
+    let response = request
+        .rollover(
+            &token_secret,
+            &new_token_secret,
+            &mut IssuerContext::new(&token_deployment(), &SystemClock, &mut nullifiers),
+            Transcript::new(b"token rollover test"),
+            rand::thread_rng(),
+        )
+        .expect("rollover should succeed");
+    let response = round_trip!(response, token::rollover::Response);
+    let _token = client_state
+        .verify_response(response)
+        .expect("response should verify");
+
+    let (client_state, request) = wallet
+        .request_rollover(
+            &wallet_params,
+            &wallet_params,
+            &token_deployment(),
+            Transcript::new(b"wallet rollover test"),
+            rand::thread_rng(),
+        )
+        .expect("rollover request should succeed");
+    let request = round_trip!(request, wallet::rollover::Request);
+    let response = request
+        .rollover(
+            &wallet_secret,
+            &wallet_secret,
+            &mut IssuerContext::new(&token_deployment(), &SystemClock, &mut nullifiers),
+            Transcript::new(b"wallet rollover test"),
+            rand::thread_rng(),
+        )
+        .expect("rollover should succeed");
+    let response = round_trip!(response, wallet::rollover::Response);
+    let wallet = client_state
+        .verify_response(response)
+        .expect("response should verify");
+
+    // Malformed encodings are rejected rather than partially decoded.
+    let (_client_state, request) = wallet
+        .request_topup(
+            1,
+            &wallet_params,
+            &token_deployment(),
+            Transcript::new(b"wallet topup test"),
+            rand::thread_rng(),
+        )
+        .expect("topup request should succeed");
+    let bytes = request.to_bytes();
+
+    let mut wrong_version = bytes.clone();
+    wrong_version[0] = 2;
+    assert!(matches!(
+        wallet::topup::Request::from_bytes(&wrong_version),
+        Err(danake::Error::WireFormatVersion(2))
+    ));
+    assert!(matches!(
+        wallet::topup::Request::from_bytes(&bytes[..bytes.len() - 1]),
+        Err(danake::Error::Encoding)
+    ));
+    let mut trailing = bytes.clone();
+    trailing.push(0);
+    assert!(matches!(
+        wallet::topup::Request::from_bytes(&trailing),
+        Err(danake::Error::Encoding)
+    ));
+    assert!(matches!(
+        wallet::topup::Request::from_bytes(&[]),
+        Err(danake::Error::Encoding)
+    ));
+}
+
+#[test]
+fn sealed_wallet_survives_restart() {
+    use danake::{wallet::*, EpochParameters};
+
+    let epoch_params = EpochParameters::from(std::time::Duration::from_secs(86400));
+    let epoch = epoch_params.epoch_at(chrono::Utc::now());
+
+    let secret = Secrets::new(epoch, rand::thread_rng());
+    let params = Parameters::from(&secret);
+
+    let (client_state, request) = Wallet::request_issuance(
+        1_000,
+        &params,
+        &deployment(),
+        Transcript::new(b"wallet issuance test"),
+        rand::thread_rng(),
+    );
+
+    let response = secret
+        .issue(
+            request,
+            &mut IssuerContext::new(
+                &deployment(),
+                &SystemClock,
+                &mut MemoryNullifierStore::new(),
+            ),
+            Transcript::new(b"wallet issuance test"),
+            rand::thread_rng(),
+        )
+        .expect("issuance should succeed");
+
+    let wallet = client_state
+        .verify_response(response)
+        .expect("response should verify");
+
+    let key = [42u8; 32];
+    let sealed = wallet.seal(&key, rand::thread_rng());
+    drop(wallet);
+
+    assert!(matches!(
+        Wallet::unseal(&sealed, &[43u8; 32]),
+        Err(danake::Error::Decryption)
+    ));
+    let mut tampered = sealed.clone();
+    *tampered.last_mut().unwrap() ^= 1;
+    assert!(matches!(
+        Wallet::unseal(&tampered, &key),
+        Err(danake::Error::Decryption)
+    ));
+
+    let wallet = Wallet::unseal(&sealed, &key).expect("sealed wallet should open");
+
+    // The bytes are: version, epoch index and duration, balance, nullifier,
+    // then the two points of the tag.
+    let bytes = wallet.to_bytes();
+    let mut zero_duration = bytes.clone();
+    zero_duration[9..17].copy_from_slice(&[0u8; 8]);
+    assert!(matches!(
+        Wallet::from_bytes(&zero_duration),
+        Err(danake::Error::MalformedCredential)
+    ));
+    let mut identity_tag = bytes.clone();
+    identity_tag[57..89].copy_from_slice(&[0u8; 32]);
+    assert!(matches!(
+        Wallet::from_bytes(&identity_tag),
+        Err(danake::Error::MalformedCredential)
+    ));
+
+    // The restored wallet is still accepted by the issuer.
+    let (client_state, request) = wallet
+        .request_topup(
+            100,
+            &params,
+            &deployment(),
+            Transcript::new(b"wallet topup test"),
+            rand::thread_rng(),
+        )
+        .expect("topup request should succeed");
+
+    let response = secret
+        .topup(
+            request,
+            &mut IssuerContext::new(
+                &deployment(),
+                &SystemClock,
+                &mut MemoryNullifierStore::new(),
+            ),
+            Transcript::new(b"wallet topup test"),
+            rand::thread_rng(),
+        )
+        .expect("topup should succeed");
+
+    client_state
+        .verify_response(response)
+        .expect("response should verify");
+}
+
+#[test]
+fn in_flight_states_survive_restart() {
+    use danake::{wallet::*, EpochParameters};
+
+    let epoch_params = EpochParameters::from(std::time::Duration::from_secs(86400));
+    let epoch = epoch_params.epoch_at(chrono::Utc::now());
+
+    let secret = Secrets::new(epoch, rand::thread_rng());
+    let params = Parameters::from(&secret);
+
+    let (client_state, _) = Wallet::request_issuance(
+        1_000,
+        &params,
+        &deployment(),
+        Transcript::new(b"wallet issuance test"),
+        rand::thread_rng(),
+    );
+    let stored = client_state.to_bytes();
+    drop(client_state);
+
+    let client_state =
+        issuance::AwaitingResponse::from_bytes(&stored, Transcript::new(b"wallet issuance test"))
+            .expect("stored state should restore");
+
+    // The request is resent after the restart.
+    let response = secret
+        .issue(
+            client_state.request().clone(),
+            &mut IssuerContext::new(
+                &deployment(),
+                &SystemClock,
+                &mut MemoryNullifierStore::new(),
+            ),
+            Transcript::new(b"wallet issuance test"),
+            rand::thread_rng(),
+        )
+        .expect("issuance should succeed");
+
+    let wallet = client_state
+        .verify_response(response)
+        .expect("response should verify");
+
+    let (client_state, request) = wallet
+        .request_topup(
+            2_000,
+            &params,
+            &deployment(),
+            Transcript::new(b"wallet topup test"),
+            rand::thread_rng(),
+        )
+        .expect("topup request should succeed");
+    let stored = client_state.to_bytes();
+    drop(client_state);
+
+    let response = secret
+        .topup(
+            request,
+            &mut IssuerContext::new(
+                &deployment(),
+                &SystemClock,
+                &mut MemoryNullifierStore::new(),
+            ),
+            Transcript::new(b"wallet topup test"),
+            rand::thread_rng(),
+        )
+        .expect("topup should succeed");
+
+    // Replaying on a differently constructed transcript fails.
+    assert!(matches!(
+        topup::AwaitingResponse::from_bytes(&stored, Transcript::new(b"other test")),
+        Err(danake::Error::ClientProof)
+    ));
+
+    let client_state =
+        topup::AwaitingResponse::from_bytes(&stored, Transcript::new(b"wallet topup test"))
+            .expect("stored state should restore");
+
+    client_state
+        .verify_response(response)
+        .expect("response should verify");
+}
+
+#[test]
+fn retried_requests_get_cached_responses() {
+    use danake::{wallet::*, EpochParameters, ResponseCache};
+
+    let epoch_params = EpochParameters::from(std::time::Duration::from_secs(86400));
+    let epoch = epoch_params.epoch_at(chrono::Utc::now());
+
+    let secret = Secrets::new(epoch, rand::thread_rng());
+    let params = Parameters::from(&secret);
+
+    let (client_state, request) = Wallet::request_issuance(
+        1_000,
+        &params,
+        &deployment(),
+        Transcript::new(b"wallet issuance test"),
+        rand::thread_rng(),
+    );
+
+    let response = secret
+        .issue(
+            request,
+            &mut IssuerContext::new(
+                &deployment(),
+                &SystemClock,
+                &mut MemoryNullifierStore::new(),
+            ),
+            Transcript::new(b"wallet issuance test"),
+            rand::thread_rng(),
+        )
+        .expect("issuance should succeed");
+
+    let wallet = client_state
+        .verify_response(response)
+        .expect("response should verify");
+
+    let (client_state, request) = wallet
+        .request_topup(
+            2_000,
+            &params,
+            &deployment(),
+            Transcript::new(b"wallet topup test"),
+            rand::thread_rng(),
+        )
+        .expect("topup request should succeed");
+
+    let mut nullifiers = MemoryNullifierStore::new();
+    let mut cache = ResponseCache::new();
+
+    let lost_response = secret
+        .topup(
+            request.clone(),
+            &mut IssuerContext::new(&deployment(), &SystemClock, &mut nullifiers)
+                .with_cache(&mut cache),
+            Transcript::new(b"wallet topup test"),
+            rand::thread_rng(),
+        )
+        .expect("topup should succeed");
+
+    // The response was lost, so the client retries the identical request.
+    let response = secret
+        .topup(
+            request.clone(),
+            &mut IssuerContext::new(&deployment(), &SystemClock, &mut nullifiers)
+                .with_cache(&mut cache),
+            Transcript::new(b"wallet topup test"),
+            rand::thread_rng(),
+        )
+        .expect("retried topup should succeed");
+    assert_eq!(response.to_bytes(), lost_response.to_bytes());
+
+    // The bytes are: wire format version, protocol version, epoch index and
+    // duration, then the amount.
+    let mut bytes = request.to_bytes();
+    bytes[19] ^= 1;
+    let altered = topup::Request::from_bytes(&bytes).expect("altered request should decode");
+    assert!(matches!(
+        secret.topup(
+            altered,
+            &mut IssuerContext::new(&deployment(), &SystemClock, &mut nullifiers)
+                .with_cache(&mut cache),
+            Transcript::new(b"wallet topup test"),
+            rand::thread_rng(),
+        ),
+        Err(danake::Error::NullifierReuse(
+            danake::CredentialType::Wallet
+        ))
+    ));
+
+    let wallet = client_state
+        .verify_response(response)
+        .expect("response should verify");
+
+    let new_epoch = epoch_params.epoch_at(chrono::Utc::now());
+    let new_secret = Secrets::new(new_epoch, rand::thread_rng());
+    let new_params = Parameters::from(&new_secret);
+
+    let (client_state, request) = wallet
+        .request_rollover(
+            &params,
+            &new_params,
+            &deployment(),
+            Transcript::new(b"wallet rollover test"),
+            rand::thread_rng(),
+        )
+        .expect("rollover request should succeed");
+
+    request
+        .rollover(
+            &secret,
+            &new_secret,
+            &mut IssuerContext::new(&deployment(), &SystemClock, &mut nullifiers)
+                .with_cache(&mut cache),
+            Transcript::new(b"wallet rollover test"),
+            rand::thread_rng(),
+        )
+        .expect("rollover should succeed");
+
+    let response = request
+        .rollover(
+            &secret,
+            &new_secret,
+            &mut IssuerContext::new(&deployment(), &SystemClock, &mut nullifiers)
+                .with_cache(&mut cache),
+            Transcript::new(b"wallet rollover test"),
+            rand::thread_rng(),
+        )
+        .expect("retried rollover should succeed");
+
+    let wallet = client_state
+        .verify_response(response)
+        .expect("response should verify");
+
+    // Every presenting endpoint shares the issuer's cache.
+    let (client_state, request) = wallet
+        .request_debit(
+            500,
+            &new_params,
+            &deployment(),
+            Transcript::new(b"wallet debit test"),
+            rand::thread_rng(),
+        )
+        .expect("debit request should succeed");
+
+    let lost_response = new_secret
+        .debit(
+            request.clone(),
+            &mut IssuerContext::new(&deployment(), &SystemClock, &mut nullifiers)
+                .with_cache(&mut cache),
+            Transcript::new(b"wallet debit test"),
+            rand::thread_rng(),
+        )
+        .expect("debit should succeed");
+
+    let response = new_secret
+        .debit(
+            request,
+            &mut IssuerContext::new(&deployment(), &SystemClock, &mut nullifiers)
+                .with_cache(&mut cache),
+            Transcript::new(b"wallet debit test"),
+            rand::thread_rng(),
+        )
+        .expect("retried debit should succeed");
+    assert_eq!(response.to_bytes(), lost_response.to_bytes());
+
+    client_state
+        .verify_response(response)
+        .expect("response should verify");
+
+    // The cached responses expire with the epoch.
+    let later = chrono::Utc::now() + chrono::Duration::days(4);
+    assert_eq!(cache.len(), 3);
+    cache.prune(later);
+    assert!(cache.is_empty());
+}
+
+#[test]
+fn manual_clock_steps_through_epoch_states() {
+    use danake::{wallet::*, CredentialType, EpochParameters, EpochState, ManualClock};
+    use danake::{Clock, NullifierStore, ResponseCache};
+
+    let day = chrono::Duration::days(1);
+    let start = chrono::DateTime::<chrono::Utc>::from(
+        std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_600_000_000),
+    );
+    let clock = ManualClock::new(start);
+
+    let epoch_params = EpochParameters::from(std::time::Duration::from_secs(86400));
+    let epoch = epoch_params.epoch_at(start + day);
+    let new_epoch = epoch_params.epoch_at(start + day * 3);
+
+    let secret = Secrets::new(epoch, rand::thread_rng());
+    let params = Parameters::from(&secret);
+    let new_secret = Secrets::new(new_epoch, rand::thread_rng());
+    let new_params = Parameters::from(&new_secret);
+
+    let (client_state, request) = Wallet::request_issuance(
+        1_000,
+        &params,
+        &deployment(),
+        Transcript::new(b"wallet issuance test"),
+        rand::thread_rng(),
+    );
+
+    let response = secret
+        .issue(
+            request,
+            &mut IssuerContext::new(
+                &deployment(),
+                &SystemClock,
+                &mut MemoryNullifierStore::new(),
+            ),
+            Transcript::new(b"wallet issuance test"),
+            rand::thread_rng(),
+        )
+        .expect("issuance should succeed");
+
+    let wallet = client_state
+        .verify_response(response)
+        .expect("response should verify");
+
+    let (client_state, request) = wallet
+        .request_rollover(
+            &params,
+            &new_params,
+            &deployment(),
+            Transcript::new(b"wallet rollover test"),
+            rand::thread_rng(),
+        )
+        .expect("rollover request should succeed");
+
+    let mut nullifiers = MemoryNullifierStore::with_clock(clock.clone());
+    let mut cache = ResponseCache::new();
+
+    let try_rollover = |nullifiers: &mut MemoryNullifierStore<ManualClock>| {
+        request.rollover(
+            &secret,
+            &new_secret,
+            &mut IssuerContext::new(&deployment(), &clock, nullifiers),
+            Transcript::new(b"wallet rollover test"),
+            rand::thread_rng(),
+        )
+    };
+
+    // The wallet's epoch is Active the day before it is Primary, but the new
+    // epoch is not yet usable.
+    assert!(matches!(epoch.state_at(clock.now()), EpochState::Active));
+    assert!(matches!(
+        try_rollover(&mut nullifiers),
+        Err(danake::Error::NewEpochState)
+    ));
+
+    clock.advance(day);
+    assert!(matches!(epoch.state_at(clock.now()), EpochState::Primary));
+    clock.advance(day);
+    assert!(matches!(epoch.state_at(clock.now()), EpochState::Active));
+    clock.advance(day);
+    assert!(matches!(epoch.state_at(clock.now()), EpochState::Rollover));
+
+    let response = request
+        .rollover(
+            &secret,
+            &new_secret,
+            &mut IssuerContext::new(&deployment(), &clock, &mut nullifiers).with_cache(&mut cache),
+            Transcript::new(b"wallet rollover test"),
+            rand::thread_rng(),
+        )
+        .expect("rollover should succeed");
+    assert_eq!(cache.len(), 1);
+
+    client_state
+        .verify_response(response)
+        .expect("response should verify");
+
+    // Once the epoch has expired, its credentials can no longer be rolled
+    // over, and its nullifiers and cached responses are dropped.
+    clock.advance(day);
+    assert!(matches!(epoch.state_at(clock.now()), EpochState::Invalid));
+    assert!(matches!(
+        try_rollover(&mut nullifiers),
+        Err(danake::Error::OldEpochState)
+    ));
+    assert!(!nullifiers
+        .reserve(CredentialType::Wallet, epoch, [1; 32])
+        .unwrap());
+    cache.prune(clock.now());
+    assert!(cache.is_empty());
+}
+
+#[test]
+fn key_ring_follows_epoch_schedule() {
+    use danake::{wallet::*, Clock, EpochParameters, KeyRing, ManualClock};
+
+    let day = chrono::Duration::days(1);
+    let clock = ManualClock::new(chrono::DateTime::<chrono::Utc>::from(
+        std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_600_000_000),
+    ));
+
+    let epoch_params = EpochParameters::from(std::time::Duration::from_secs(86400));
+    let mut ring = KeyRing::<Secrets>::new(epoch_params);
+    ring.update(clock.now(), rand::thread_rng());
+
+    // The next epoch's parameters are published while they are Active.
+    let published = ring.parameters();
+    assert_eq!(published.len(), 2);
+
+    let secret = ring.primary(clock.now()).expect("primary secrets exist");
+    let params = Parameters::from(secret);
+    assert_eq!(params, published[0]);
+    let epoch = epoch_params.epoch_at(clock.now());
+
+    let (client_state, request) = Wallet::request_issuance(
+        1_000,
+        &params,
+        &deployment(),
+        Transcript::new(b"wallet issuance test"),
+        rand::thread_rng(),
+    );
+
+    let response = secret
+        .issue(
+            request,
+            &mut IssuerContext::new(
+                &deployment(),
+                &SystemClock,
+                &mut MemoryNullifierStore::new(),
+            ),
+            Transcript::new(b"wallet issuance test"),
+            rand::thread_rng(),
+        )
+        .expect("issuance should succeed");
+
+    let wallet = client_state
+        .verify_response(response)
+        .expect("response should verify");
+
+    clock.advance(day);
+    ring.update(clock.now(), rand::thread_rng());
+    assert_eq!(ring.parameters().len(), 3);
+    let new_params = Parameters::from(ring.primary(clock.now()).unwrap());
+    assert_eq!(new_params, published[1]);
+
+    // The wallet is rolled over once its epoch is in the Rollover state.
+    clock.advance(day);
+    ring.update(clock.now(), rand::thread_rng());
+    assert_eq!(ring.parameters().len(), 4);
+
+    let (client_state, request) = wallet
+        .request_rollover(
+            &params,
+            &new_params,
+            &deployment(),
+            Transcript::new(b"wallet rollover test"),
+            rand::thread_rng(),
+        )
+        .expect("rollover request should succeed");
+
+    let response = request
+        .rollover(
+            ring.verifying(request.epoch(), clock.now())
+                .expect("old secrets are held"),
+            ring.get(request.new_epoch()).expect("new secrets are held"),
+            &mut IssuerContext::new(
+                &deployment(),
+                &clock,
+                &mut MemoryNullifierStore::with_clock(&clock),
+            ),
+            Transcript::new(b"wallet rollover test"),
+            rand::thread_rng(),
+        )
+        .expect("rollover should succeed");
+
+    client_state
+        .verify_response(response)
+        .expect("response should verify");
+
+    // The secrets are dropped once their epoch expires.
+    clock.advance(day);
+    assert!(ring.verifying(epoch, clock.now()).is_none());
+    assert!(ring.get(epoch).is_some());
+    ring.update(clock.now(), rand::thread_rng());
+    assert!(ring.get(epoch).is_none());
+    assert_eq!(ring.parameters().len(), 4);
+}
+
+#[test]
+fn key_ring_rejects_forged_request_epochs() {
+    use danake::{wallet::*, Clock, EpochParameters, KeyRing, ManualClock};
+
+    let clock = ManualClock::new(chrono::DateTime::<chrono::Utc>::from(
+        std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_600_000_000),
+    ));
+
+    let epoch_params = EpochParameters::from(std::time::Duration::from_secs(86400));
+    let mut ring = KeyRing::<Secrets>::new(epoch_params);
+    ring.update(clock.now(), rand::thread_rng());
+    let secret = ring.primary(clock.now()).expect("primary secrets exist");
+    let params = Parameters::from(secret);
+
+    let (client_state, request) = Wallet::request_issuance(
+        1_000,
+        &params,
+        &deployment(),
+        Transcript::new(b"wallet issuance test"),
+        rand::thread_rng(),
+    );
+    let response = secret
+        .issue(
+            request,
+            &mut IssuerContext::new(
+                &deployment(),
+                &clock,
+                &mut MemoryNullifierStore::with_clock(&clock),
+            ),
+            Transcript::new(b"wallet issuance test"),
+            rand::thread_rng(),
+        )
+        .expect("issuance should succeed");
+    let wallet = client_state
+        .verify_response(response)
+        .expect("response should verify");
+
+    let (_, request) = wallet
+        .request_topup(
+            2_000,
+            &params,
+            &deployment(),
+            Transcript::new(b"wallet topup test"),
+            rand::thread_rng(),
+        )
+        .expect("topup request should succeed");
+    assert!(ring.verifying(request.epoch(), clock.now()).is_some());
+
+    // The epoch follows the wire format version and the protocol version,
+    // as an index and then a duration.  A zero duration would divide by
+    // zero in the epoch arithmetic, so the request fails to decode.
+    let bytes = request.to_bytes();
+    let mut zero = bytes.clone();
+    zero[11..19].copy_from_slice(&0u64.to_le_bytes());
+    assert!(matches!(
+        topup::Request::from_bytes(&zero),
+        Err(danake::Error::Encoding)
+    ));
+
+    // An epoch on another schedule is never looked up in the ring.
+    let mut hourly = bytes;
+    hourly[11..19].copy_from_slice(&3600u64.to_le_bytes());
+    let forged = topup::Request::from_bytes(&hourly).expect("request should decode");
+    assert!(ring.verifying(forged.epoch(), clock.now()).is_none());
+}
+
+#[test]
+fn presentations_accept_active_epochs() {
+    use danake::{wallet::*, Clock, EpochParameters, KeyRing, ManualClock};
+
+    let day = chrono::Duration::days(1);
+    let start = chrono::DateTime::<chrono::Utc>::from(
+        std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_600_000_000),
+    );
+    let clock = ManualClock::new(start - day);
+
+    let epoch_params = EpochParameters::from(std::time::Duration::from_secs(86400));
+    let epoch = epoch_params.epoch_at(start);
+    let mut ring = KeyRing::<Secrets>::new(epoch_params);
+    ring.update(clock.now(), rand::thread_rng());
+    let mut nullifiers = MemoryNullifierStore::with_clock(clock.clone());
+
+    // A client whose clock is ahead uses the next epoch's parameters while
+    // they are still Active.
+    let params = Parameters::from(ring.get(epoch).expect("next secrets are published"));
+
+    let (client_state, request) = Wallet::request_issuance(
+        1_000,
+        &params,
+        &deployment(),
+        Transcript::new(b"wallet issuance test"),
+        rand::thread_rng(),
+    );
+
+    let response = ring
+        .get(epoch)
+        .unwrap()
+        .issue(
+            request,
+            &mut IssuerContext::new(
+                &deployment(),
+                &SystemClock,
+                &mut MemoryNullifierStore::new(),
+            ),
+            Transcript::new(b"wallet issuance test"),
+            rand::thread_rng(),
+        )
+        .expect("issuance should succeed");
+
+    let mut wallet = client_state
+        .verify_response(response)
+        .expect("response should verify");
+
+    // The wallet can be topped up while its epoch is Active, Primary, or
+    // Active again.
+    for _ in 0..3 {
+        let (client_state, request) = wallet
+            .request_topup(
+                100,
+                &params,
+                &deployment(),
+                Transcript::new(b"wallet topup test"),
+                rand::thread_rng(),
+            )
+            .expect("topup request should succeed");
+
+        let response = ring
+            .get(request.epoch())
+            .expect("secrets are held")
+            .topup(
+                request,
+                &mut IssuerContext::new(&deployment(), &clock, &mut nullifiers),
+                Transcript::new(b"wallet topup test"),
+                rand::thread_rng(),
+            )
+            .expect("topup should succeed");
+
+        wallet = client_state
+            .verify_response(response)
+            .expect("response should verify");
+
+        clock.advance(day);
+        ring.update(clock.now(), rand::thread_rng());
+    }
+
+    // Once the epoch is in the Rollover state, the wallet must be rolled
+    // over instead.
+    let (_, request) = wallet
+        .request_topup(
+            100,
+            &params,
+            &deployment(),
+            Transcript::new(b"wallet topup test"),
+            rand::thread_rng(),
+        )
+        .expect("topup request should succeed");
+
+    assert!(matches!(
+        ring.get(request.epoch()).unwrap().topup(
+            request,
+            &mut IssuerContext::new(&deployment(), &clock, &mut nullifiers),
+            Transcript::new(b"wallet topup test"),
+            rand::thread_rng()
+        ),
+        Err(danake::Error::RolloverRequired)
+    ));
+}
+
+#[test]
+fn secrets_derived_from_seed_match_known_answers() {
+    use danake::{token, wallet, EpochParameters, KeyRing, MasterSeed};
+
+    let time = chrono::DateTime::<chrono::Utc>::from(
+        std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_600_000_000),
+    );
+    let wallet_epoch_params = EpochParameters::from(std::time::Duration::from_secs(86400));
+    let token_epoch_params = EpochParameters::from(std::time::Duration::from_secs(3600));
+    let wallet_epoch = wallet_epoch_params.epoch_at(time);
+    let token_epoch = token_epoch_params.epoch_at(time);
+    let seed = MasterSeed::from_bytes([7; 32]);
+
+    // The issuer parameters X_0, X_1, X_2 are the first 96 bytes of the
+    // parameter encoding.
+    let hex = |params: Vec<u8>| -> Vec<String> {
+        params[..96]
+            .chunks(32)
+            .map(|point| point.iter().map(|b| format!("{:02x}", b)).collect())
+            .collect()
+    };
+
+    let wallet_params = wallet::Parameters::from(&wallet::Secrets::derive(&seed, wallet_epoch));
+    assert_eq!(
+        hex(bincode::serialize(&wallet_params).unwrap()),
+        [
+            "3e67c21a07f36886fa662c4d6474f03ba499618044487746d3a6f66ce7cc2d7b",
+            "b669cb268bc68b2d3765683aaddbec4a6e6fbeec37a086dfbfa68187626dd526",
+            "bae34f3356247d849e30ce1157089be8cbec5627f8a0ce030f612824dc99a96c",
+        ]
+    );
+
+    let token_params = token::Parameters::from(&token::Secrets::derive(&seed, token_epoch, 16));
+    assert_eq!(
+        hex(bincode::serialize(&token_params).unwrap()),
+        [
+            "72110c70e9face9d3296d238701544ca8b38fdd8a5e66c4f782d13e7ea4f554b",
+            "8e196629bc27d231b32e437bedd995d3cb7983918fb6b321745e062eef4bb233",
+            "ba715b8cbbf8c35724da33a47ecd7b8abff56cd3de81edbf4ff2d319a71aeb7f",
+        ]
+    );
+
+    // Each epoch has its own secrets.
+    let next_params = wallet::Parameters::from(&wallet::Secrets::derive(
+        &seed,
+        wallet_epoch_params.epoch_at(time + chrono::Duration::days(1)),
+    ));
+    assert_ne!(
+        bincode::serialize(&next_params).unwrap()[..96],
+        bincode::serialize(&wallet_params).unwrap()[..96]
+    );
+
+    // A replacement issuer with the same seed holds the same secrets.
+    let mut ring = KeyRing::<wallet::Secrets>::from_seed(wallet_epoch_params, seed.clone());
+    ring.update(time, rand::thread_rng());
+    let mut replacement = KeyRing::<wallet::Secrets>::from_seed(wallet_epoch_params, seed);
+    replacement.update(time, rand::thread_rng());
+    assert_eq!(ring.parameters(), replacement.parameters());
+    assert_eq!(ring.parameters()[0], wallet_params);
+}
+
+#[test]
+fn parameter_schedule_decides_when_to_roll_over() {
+    use danake::{wallet::*, Clock, EpochParameters, KeyRing, ManualClock};
+
+    let day = chrono::Duration::days(1);
+    let clock = ManualClock::new(chrono::DateTime::<chrono::Utc>::from(
+        std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_600_000_000),
+    ));
+
+    let epoch_params = EpochParameters::from(std::time::Duration::from_secs(86400));
+    let mut ring = KeyRing::<Secrets>::new(epoch_params);
+    let mut schedule = ParameterSchedule::new(epoch_params);
+    let mut nullifiers = MemoryNullifierStore::with_clock(clock.clone());
+
+    let publish = |ring: &mut KeyRing<Secrets>, schedule: &mut ParameterSchedule| {
+        ring.update(clock.now(), rand::thread_rng());
+        for params in ring.parameters() {
+            schedule.insert(params).expect("parameters are consistent");
+        }
+        schedule.prune(clock.now());
+    };
+    publish(&mut ring, &mut schedule);
+
+    let params = *schedule.primary(clock.now()).expect("primary is published");
+    let (client_state, request) = Wallet::request_issuance(
+        1_000,
+        &params,
+        &deployment(),
+        Transcript::new(b"wallet issuance test"),
+        rand::thread_rng(),
+    );
+
+    let response = ring
+        .primary(clock.now())
+        .unwrap()
+        .issue(
+            request,
+            &mut IssuerContext::new(
+                &deployment(),
+                &SystemClock,
+                &mut MemoryNullifierStore::new(),
+            ),
+            Transcript::new(b"wallet issuance test"),
+            rand::thread_rng(),
+        )
+        .expect("issuance should succeed");
+
+    let wallet = client_state
+        .verify_response(response)
+        .expect("response should verify");
+    assert_eq!(wallet.balance(), 1_000);
+    assert_eq!(wallet.epoch(), params.epoch());
+
+    assert!(matches!(
+        schedule.decide(&wallet, clock.now()),
+        Ok(Decision::Use(p)) if *p == params
+    ));
+
+    clock.advance(day);
+    publish(&mut ring, &mut schedule);
+
+    let (old, new) = match schedule.decide(&wallet, clock.now()) {
+        Ok(Decision::RollOver { old, new }) => (*old, *new),
+        _ => panic!("wallet should be rolled over"),
+    };
+    assert_eq!(old, params);
+
+    let (client_state, request) = wallet
+        .request_rollover(
+            &old,
+            &new,
+            &deployment(),
+            Transcript::new(b"wallet rollover test"),
+            rand::thread_rng(),
+        )
+        .expect("rollover request should succeed");
+
+    let response = request
+        .rollover(
+            ring.get(request.epoch()).unwrap(),
+            ring.get(request.new_epoch()).unwrap(),
+            &mut IssuerContext::new(&deployment(), &clock, &mut nullifiers),
+            Transcript::new(b"wallet rollover test"),
+            rand::thread_rng(),
+        )
+        .expect("rollover should succeed");
+
+    let wallet = client_state
+        .verify_response(response)
+        .expect("response should verify");
+    assert_eq!(wallet.balance(), 1_000);
+    assert!(matches!(
+        schedule.decide(&wallet, clock.now()),
+        Ok(Decision::Use(p)) if *p == new
+    ));
+
+    // Different parameters for a known epoch are rejected.
+    let other = Parameters::from(&Secrets::new(new.epoch(), rand::thread_rng()));
+    assert!(matches!(
+        schedule.insert(other),
+        Err(danake::Error::ConflictingParameters)
+    ));
+
+    clock.advance(day * 3);
+    assert!(matches!(
+        schedule.decide(&wallet, clock.now()),
+        Ok(Decision::Expired)
+    ));
+}
+
+#[test]
+fn offline_wallet_rolls_over_across_several_epochs() {
+    use danake::{
+        wallet::*, Clock, EpochParameters, KeyRing, ManualClock, MasterSeed, RolloverPolicy,
+    };
+
+    let day = chrono::Duration::days(1);
+    let clock = ManualClock::new(chrono::DateTime::<chrono::Utc>::from(
+        std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_600_000_000),
+    ));
+
+    let policy = RolloverPolicy::new(5);
+    let epoch_params = EpochParameters::from(std::time::Duration::from_secs(86400));
+    let seed = MasterSeed::generate(rand::thread_rng());
+    let mut ring =
+        KeyRing::<Secrets>::from_seed(epoch_params, seed.clone()).with_rollover_policy(policy);
+    ring.update(clock.now(), rand::thread_rng());
+
+    let secret = ring.primary(clock.now()).expect("primary secrets exist");
+    let params = Parameters::from(secret);
+
+    let mut wallets = (0..2).map(|_| {
+        let (client_state, request) = Wallet::request_issuance(
+            1_000,
+            &params,
+            &deployment(),
+            Transcript::new(b"wallet issuance test"),
+            rand::thread_rng(),
+        );
+        let response = secret
+            .issue(
+                request,
+                &mut IssuerContext::new(
+                    &deployment(),
+                    &SystemClock,
+                    &mut MemoryNullifierStore::new(),
+                ),
+                Transcript::new(b"wallet issuance test"),
+                rand::thread_rng(),
+            )
+            .expect("issuance should succeed");
+        client_state
+            .verify_response(response)
+            .expect("response should verify")
+    });
+    let wallet = wallets.next().unwrap();
+    let stale_wallet = wallets.next().unwrap();
+
+    // The client comes back online after four epochs, past the Rollover
+    // state, and rolls over directly into the current epoch.
+    for _ in 0..4 {
+        clock.advance(day);
+        ring.update(clock.now(), rand::thread_rng());
+    }
+    let new_params = Parameters::from(ring.primary(clock.now()).unwrap());
+
+    let (client_state, request) = wallet
+        .request_rollover(
+            &params,
+            &new_params,
+            &deployment(),
+            Transcript::new(b"wallet rollover test"),
+            rand::thread_rng(),
+        )
+        .expect("rollover request should succeed");
+
+    // An issuer following the standard key schedule rejects the rollover.
+    let old_epoch = request.epoch();
+    let result = request.rollover(
+        ring.verifying(old_epoch, clock.now())
+            .expect("old secrets are held"),
+        ring.get(request.new_epoch()).expect("new secrets are held"),
+        &mut IssuerContext::new(
+            &deployment(),
+            &clock,
+            &mut MemoryNullifierStore::with_clock(&clock),
+        ),
+        Transcript::new(b"wallet rollover test"),
+        rand::thread_rng(),
+    );
+    assert!(matches!(result, Err(danake::Error::OldEpochState)));
+
+    let mut nullifiers = MemoryNullifierStore::with_clock(&clock).with_rollover_policy(policy);
+    let response = request
+        .rollover(
+            ring.verifying(request.epoch(), clock.now())
+                .expect("old secrets are held"),
+            ring.get(request.new_epoch()).expect("new secrets are held"),
+            &mut IssuerContext::new(&deployment(), &clock, &mut nullifiers),
+            Transcript::new(b"wallet rollover test"),
+            rand::thread_rng(),
+        )
+        .expect("rollover should succeed");
+    let new_wallet = client_state
+        .verify_response(response)
+        .expect("response should verify");
+    assert_eq!(new_wallet.epoch(), epoch_params.epoch_at(clock.now()));
+    assert_eq!(new_wallet.balance(), 1_000);
+
+    // Once the wallet is older than the policy allows, its secrets are
+    // dropped and the rollover is rejected.
+    clock.advance(day * 2);
+    ring.update(clock.now(), rand::thread_rng());
+    assert!(ring.verifying(old_epoch, clock.now()).is_none());
+
+    let newest_params = Parameters::from(ring.primary(clock.now()).unwrap());
+    let (_, request) = stale_wallet
+        .request_rollover(
+            &params,
+            &newest_params,
+            &deployment(),
+            Transcript::new(b"wallet rollover test"),
+            rand::thread_rng(),
+        )
+        .expect("rollover request should succeed");
+    let result = request.rollover(
+        &Secrets::derive(&seed, old_epoch),
+        ring.get(request.new_epoch()).expect("new secrets are held"),
+        &mut IssuerContext::new(&deployment(), &clock, &mut nullifiers),
+        Transcript::new(b"wallet rollover test"),
+        rand::thread_rng(),
+    );
+    assert!(matches!(result, Err(danake::Error::OldEpochState)));
+}
+
+#[test]
+fn changed_public_inputs_fail_verification() {
+    use danake::{wallet::*, EpochParameters};
+
+    let epoch_params = EpochParameters::from(std::time::Duration::from_secs(86400));
+    let epoch = epoch_params.epoch_at(chrono::Utc::now());
+    let secret = Secrets::new(epoch, rand::thread_rng());
+    let other_secret = Secrets::new(epoch, rand::thread_rng());
+    let params = Parameters::from(&secret);
+
+    // Flip the lowest bit of the encoded request at `offset`, which skips
+    // the one-byte wire format version and the two-byte protocol version.
+    fn tamper(bytes: &[u8], offset: usize) -> Vec<u8> {
+        let mut bytes = bytes.to_vec();
+        bytes[3 + offset] ^= 1;
+        bytes
+    }
+
+    let (client_state, request) = Wallet::request_issuance(
+        1_000,
+        &params,
+        &deployment(),
+        Transcript::new(b"wallet issuance test"),
+        rand::thread_rng(),
+    );
+    let bytes = request.to_bytes();
+
+    // The requested amount is the first field of an issuance request.
+    let result = secret.issue(
+        issuance::Request::from_bytes(&tamper(&bytes, 0)).unwrap(),
+        &mut IssuerContext::new(
+            &deployment(),
+            &SystemClock,
+            &mut MemoryNullifierStore::new(),
+        ),
+        Transcript::new(b"wallet issuance test"),
+        rand::thread_rng(),
+    );
+    assert!(matches!(result, Err(danake::Error::ClientProof)));
+
+    // Issuer parameters for the same epoch but a different key.
+    let result = other_secret.issue(
+        request.clone(),
+        &mut IssuerContext::new(
+            &deployment(),
+            &SystemClock,
+            &mut MemoryNullifierStore::new(),
+        ),
+        Transcript::new(b"wallet issuance test"),
+        rand::thread_rng(),
+    );
+    assert!(matches!(result, Err(danake::Error::ClientProof)));
+
+    let response = secret
+        .issue(
+            request,
+            &mut IssuerContext::new(
+                &deployment(),
+                &SystemClock,
+                &mut MemoryNullifierStore::new(),
+            ),
+            Transcript::new(b"wallet issuance test"),
+            rand::thread_rng(),
+        )
+        .expect("issuance should succeed");
+    let wallet = client_state
+        .verify_response(response)
+        .expect("response should verify");
+
+    let (client_state, request) = wallet
+        .request_topup(
+            500,
+            &params,
+            &deployment(),
+            Transcript::new(b"wallet topup test"),
+            rand::thread_rng(),
+        )
+        .expect("topup request should succeed");
+    let bytes = request.to_bytes();
+
+    // A topup request starts with the epoch, then the amount `c` and the
+    // nullifier `n`.
+    let mut nullifiers = MemoryNullifierStore::new();
+    for &offset in &[16, 24] {
+        let result = secret.topup(
+            topup::Request::from_bytes(&tamper(&bytes, offset)).unwrap(),
+            &mut IssuerContext::new(&deployment(), &SystemClock, &mut nullifiers),
+            Transcript::new(b"wallet topup test"),
+            rand::thread_rng(),
+        );
+        assert!(matches!(result, Err(danake::Error::ClientProof)));
+    }
+
+    let response = secret
+        .topup(
+            request,
+            &mut IssuerContext::new(&deployment(), &SystemClock, &mut nullifiers),
+            Transcript::new(b"wallet topup test"),
+            rand::thread_rng(),
+        )
+        .expect("topup should succeed");
+    let wallet = client_state
+        .verify_response(response)
+        .expect("response should verify");
+
+    // The new parameters of a rollover are bound, so an issuer cannot issue
+    // the new wallet under a different key than the client expects.
+    let new_epoch = epoch_params.epoch_at(chrono::Utc::now() + chrono::Duration::days(1));
+    let new_secret = Secrets::new(new_epoch, rand::thread_rng());
+    let other_new_secret = Secrets::new(new_epoch, rand::thread_rng());
+    let new_params = Parameters::from(&new_secret);
+
+    let (client_state, request) = wallet
+        .request_rollover(
+            &params,
+            &new_params,
+            &deployment(),
+            Transcript::new(b"wallet rollover test"),
+            rand::thread_rng(),
+        )
+        .expect("rollover request should succeed");
+
+    let result = request.rollover(
+        &secret,
+        &other_new_secret,
+        &mut IssuerContext::new(&deployment(), &SystemClock, &mut nullifiers),
+        Transcript::new(b"wallet rollover test"),
+        rand::thread_rng(),
+    );
+    assert!(matches!(result, Err(danake::Error::ClientProof)));
+
+    let response = request
+        .rollover(
+            &secret,
+            &new_secret,
+            &mut IssuerContext::new(&deployment(), &SystemClock, &mut nullifiers),
+            Transcript::new(b"wallet rollover test"),
+            rand::thread_rng(),
+        )
+        .expect("rollover should succeed");
+    let wallet = client_state
+        .verify_response(response)
+        .expect("response should verify");
+    assert_eq!(wallet.balance(), 1_500);
+}
+
+#[test]
+fn transcript_binding_rejects_relabelled_token_requests() {
+    use danake::{token, wallet, EpochParameters, PROTOCOL_VERSION};
+    use rand::{rngs::StdRng, SeedableRng};
+
+    let epoch_params = EpochParameters::from(std::time::Duration::from_secs(86400));
+    let now = chrono::Utc::now();
+    let epoch = epoch_params.epoch_at(now - chrono::Duration::days(1));
+    let next_epoch = epoch_params.epoch_at(now);
+    let later_epoch = epoch_params.epoch_at(now + chrono::Duration::days(1));
+
+    // Secrets generated from the same randomness share a key across epochs,
+    // as they would for an issuer which reused its keys by mistake.  The
+    // proof equations only involve the key, so relabelling a request with
+    // the other epoch is only caught by binding the epoch to the transcript.
+    let wallet_secret = wallet::Secrets::new(epoch, StdRng::seed_from_u64(1));
+    let reused_wallet_secret = wallet::Secrets::new(next_epoch, StdRng::seed_from_u64(1));
+    let wallet_params = wallet::Parameters::from(&wallet_secret);
+    let token_secret = token::Secrets::new(epoch, 32, StdRng::seed_from_u64(2));
+    let reused_token_secret = token::Secrets::new(next_epoch, 32, StdRng::seed_from_u64(2));
+    let token_params = token::Parameters::from(&token_secret);
+    let new_token_secret = token::Secrets::new(next_epoch, 32, StdRng::seed_from_u64(3));
+    let reused_new_token_secret = token::Secrets::new(later_epoch, 32, StdRng::seed_from_u64(3));
+    let new_token_params = token::Parameters::from(&new_token_secret);
+
+    // Move the epoch whose index starts at `offset` in an encoded request to
+    // the following epoch.
+    fn next(bytes: &[u8], offset: usize) -> Vec<u8> {
+        let mut bytes = bytes.to_vec();
+        let mut index = [0u8; 8];
+        index.copy_from_slice(&bytes[offset..offset + 8]);
+        let index = i64::from_le_bytes(index) + 1;
+        bytes[offset..offset + 8].copy_from_slice(&index.to_le_bytes());
+        bytes
+    }
+
+    // Relabel an encoded request with the previous protocol version, which
+    // follows the one-byte wire format version.
+    fn older(bytes: &[u8]) -> Vec<u8> {
+        let mut bytes = bytes.to_vec();
+        bytes[1..3].copy_from_slice(&(PROTOCOL_VERSION - 1).to_le_bytes());
+        bytes
+    }
+
+    // An issuer still accepting the previous version during a migration.
+    let window = deployment().with_accepted_versions(vec![PROTOCOL_VERSION - 1, PROTOCOL_VERSION]);
+    let mut nullifiers = MemoryNullifierStore::new();
+
+    let (client_state, request) = wallet::Wallet::request_issuance(
+        1_000,
+        &wallet_params,
+        &deployment(),
+        Transcript::new(b"wallet issuance test"),
+        rand::thread_rng(),
+    );
+    let response = wallet_secret
+        .issue(
+            request,
+            &mut IssuerContext::new(&deployment(), &SystemClock, &mut nullifiers),
+            Transcript::new(b"wallet issuance test"),
+            rand::thread_rng(),
+        )
+        .expect("issuance should succeed");
+    let wallet = client_state
+        .verify_response(response)
+        .expect("response should verify");
+
+    // A purchase request starts with the wallet epoch, then the token epoch.
+    let (client_state, request) = wallet
+        .request_token_purchase(
+            100,
+            &wallet_params,
+            &token_params,
+            &deployment(),
+            Transcript::new(b"token purchase test"),
+            rand::thread_rng(),
+        )
+        .expect("purchase request should succeed");
+    let bytes = request.to_bytes();
+    let cases = [
+        (next(&bytes, 3), &reused_wallet_secret, &token_secret),
+        (next(&bytes, 19), &wallet_secret, &reused_token_secret),
+        (older(&bytes), &wallet_secret, &token_secret),
+    ];
+    for (bytes, wallet_secret, token_secret) in cases.iter() {
+        let result = token::purchase::Request::from_bytes(bytes)
+            .unwrap()
+            .purchase(
+                wallet_secret,
+                token_secret,
+                &mut IssuerContext::new(&window, &SystemClock, &mut nullifiers),
+                Transcript::new(b"token purchase test"),
+                rand::thread_rng(),
+            );
+        assert!(matches!(result, Err(danake::Error::ClientProof)));
+    }
+    let response = request
+        .purchase(
+            &wallet_secret,
+            &token_secret,
+            &mut IssuerContext::new(&window, &SystemClock, &mut nullifiers),
+            Transcript::new(b"token purchase test"),
+            rand::thread_rng(),
+        )
+        .expect("purchase should succeed");
+    let (_, token) = client_state
+        .verify_response(response)
+        .expect("response should verify");
+
+    // A spend request starts with the token epoch.
+    let (client_state, request) = token
+        .request_spend(
+            40,
+            &token_params,
+            &deployment(),
+            Transcript::new(b"token spend test"),
+            rand::thread_rng(),
+        )
+        .expect("spend request should succeed");
+    let bytes = request.to_bytes();
+    let cases = [
+        (next(&bytes, 3), &reused_token_secret),
+        (older(&bytes), &token_secret),
+    ];
+    for (bytes, secret) in cases.iter() {
+        let result = secret.spend(
+            token::spend::Request::from_bytes(bytes).unwrap(),
+            &mut IssuerContext::new(&window, &SystemClock, &mut nullifiers),
+            Transcript::new(b"token spend test"),
+            rand::thread_rng(),
+        );
+        assert!(matches!(result, Err(danake::Error::ClientProof)));
+    }
+    let response = token_secret
+        .spend(
+            request,
+            &mut IssuerContext::new(&window, &SystemClock, &mut nullifiers),
+            Transcript::new(b"token spend test"),
+            rand::thread_rng(),
+        )
+        .expect("spend should succeed");
+    let token = client_state
+        .verify_response(response)
+        .expect("response should verify");
+
+    // A rollover request starts with the old epoch, then the new epoch.
+    let (client_state, request) = token
+        .request_rollover(
+            &token_params,
+            &new_token_params,
+            &deployment(),
+            Transcript::new(b"token rollover test"),
+            rand::thread_rng(),
+        )
+        .expect("rollover request should succeed");
+    let bytes = request.to_bytes();
+    let cases = [
+        (next(&bytes, 3), &reused_token_secret, &new_token_secret),
+        (next(&bytes, 19), &token_secret, &reused_new_token_secret),
+        (older(&bytes), &token_secret, &new_token_secret),
+    ];
+    for (bytes, old_secret, new_secret) in cases.iter() {
+        let result = token::rollover::Request::from_bytes(bytes)
+            .unwrap()
+            .rollover(
+                old_secret,
+                new_secret,
+                &mut IssuerContext::new(&window, &SystemClock, &mut nullifiers),
+                Transcript::new(b"token rollover test"),
+                rand::thread_rng(),
+            );
+        assert!(matches!(result, Err(danake::Error::ClientProof)));
+    }
+    let response = request
+        .rollover(
+            &token_secret,
+            &new_token_secret,
+            &mut IssuerContext::new(&window, &SystemClock, &mut nullifiers),
+            Transcript::new(b"token rollover test"),
+            rand::thread_rng(),
+        )
+        .expect("rollover should succeed");
+    client_state
+        .verify_response(response)
+        .expect("response should verify");
+}
+
+#[test]
+fn requests_fail_verification_in_other_deployments() {
+    use danake::{wallet::*, DeploymentId, EpochParameters};
+
+    let epoch_params = EpochParameters::from(std::time::Duration::from_secs(86400));
+    let epoch = epoch_params.epoch_at(chrono::Utc::now());
+    let secret = Secrets::new(epoch, rand::thread_rng());
+    let params = Parameters::from(&secret);
+
+    let production = DeploymentId::new("production", epoch_params, epoch_params, 32);
+    let staging = DeploymentId::new("staging", epoch_params, epoch_params, 32);
+    // The same name with a different parameter set is another deployment.
+    let reconfigured = DeploymentId::new("production", epoch_params, epoch_params, 64);
+    assert_ne!(production, reconfigured);
+
+    let (client_state, request) = Wallet::request_issuance(
+        1_000,
+        &params,
+        &production,
+        Transcript::new(b"wallet issuance test"),
+        rand::thread_rng(),
+    );
+
+    // The issuer's keys are shared by mistake, but the deployments differ.
+    for other in &[&staging, &reconfigured] {
+        let result = secret.issue(
+            request.clone(),
+            &mut IssuerContext::new(other, &SystemClock, &mut MemoryNullifierStore::new()),
+            Transcript::new(b"wallet issuance test"),
+            rand::thread_rng(),
+        );
+        assert!(matches!(result, Err(danake::Error::ClientProof)));
+    }
+
+    let response = secret
+        .issue(
+            request,
+            &mut IssuerContext::new(&production, &SystemClock, &mut MemoryNullifierStore::new()),
+            Transcript::new(b"wallet issuance test"),
+            rand::thread_rng(),
+        )
+        .expect("issuance should succeed");
+    let wallet = client_state
+        .verify_response(response)
+        .expect("response should verify");
+
+    let (_, request) = wallet
+        .request_topup(
+            500,
+            &params,
+            &production,
+            Transcript::new(b"wallet topup test"),
+            rand::thread_rng(),
+        )
+        .expect("topup request should succeed");
+
+    let mut nullifiers = MemoryNullifierStore::new();
+    let result = secret.topup(
+        request.clone(),
+        &mut IssuerContext::new(&staging, &SystemClock, &mut nullifiers),
+        Transcript::new(b"wallet topup test"),
+        rand::thread_rng(),
+    );
+    assert!(matches!(result, Err(danake::Error::ClientProof)));
+
+    secret
+        .topup(
+            request,
+            &mut IssuerContext::new(&production, &SystemClock, &mut nullifiers),
+            Transcript::new(b"wallet topup test"),
+            rand::thread_rng(),
+        )
+        .expect("topup should succeed");
+}
+
+#[test]
+fn issuers_reject_secrets_from_other_parameter_sets() {
+    use danake::{token, wallet, DeploymentId, EpochParameters};
+
+    let day = EpochParameters::from(std::time::Duration::from_secs(86400));
+    let hour = EpochParameters::from(std::time::Duration::from_secs(3600));
+    let wallet_secret = wallet::Secrets::new(day.epoch_at(chrono::Utc::now()), rand::thread_rng());
+    let wallet_params = wallet::Parameters::from(&wallet_secret);
+    // Token balances are proved in 16 bits, but the deployment uses 32.
+    let token_secret =
+        token::Secrets::new(day.epoch_at(chrono::Utc::now()), 16, rand::thread_rng());
+    let token_params = token::Parameters::from(&token_secret);
+    // Wallet epochs last an hour in this deployment, but a day for the keys.
+    let hourly = DeploymentId::new("test", hour, day, 32);
+
+    let (_, request) = wallet::Wallet::request_issuance(
+        1_000,
+        &wallet_params,
+        &hourly,
+        Transcript::new(b"wallet issuance test"),
+        rand::thread_rng(),
+    );
+    // Issuance reveals no nullifier, so it needs no nullifier store.
+    let result = wallet_secret.issue(
+        request,
+        &IssuerContext::new(&hourly, &SystemClock, &mut ()),
+        Transcript::new(b"wallet issuance test"),
+        rand::thread_rng(),
+    );
+    assert!(matches!(result, Err(danake::Error::DeploymentMismatch)));
+
+    let mut wallets = (0..2).map(|_| {
+        let (client_state, request) = wallet::Wallet::request_issuance(
+            1_000,
+            &wallet_params,
+            &deployment(),
+            Transcript::new(b"wallet issuance test"),
+            rand::thread_rng(),
+        );
+        let response = wallet_secret
+            .issue(
+                request,
+                &IssuerContext::new(&deployment(), &SystemClock, &mut ()),
+                Transcript::new(b"wallet issuance test"),
+                rand::thread_rng(),
+            )
+            .expect("issuance should succeed");
+        client_state
+            .verify_response(response)
+            .expect("response should verify")
+    });
+
+    let mut nullifiers = MemoryNullifierStore::new();
+    let (_, request) = wallets
+        .next()
+        .unwrap()
+        .request_topup(
+            500,
+            &wallet_params,
+            &hourly,
+            Transcript::new(b"wallet topup test"),
+            rand::thread_rng(),
+        )
+        .expect("topup request should succeed");
+    let result = wallet_secret.topup(
+        request,
+        &mut IssuerContext::new(&hourly, &SystemClock, &mut nullifiers),
+        Transcript::new(b"wallet topup test"),
+        rand::thread_rng(),
+    );
+    assert!(matches!(result, Err(danake::Error::DeploymentMismatch)));
+
+    let (_, request) = wallets
+        .next()
+        .unwrap()
+        .request_token_purchase(
+            100,
+            &wallet_params,
+            &token_params,
+            &deployment(),
+            Transcript::new(b"token purchase test"),
+            rand::thread_rng(),
+        )
+        .expect("purchase request should succeed");
+    let result = request.purchase(
+        &wallet_secret,
+        &token_secret,
+        &mut IssuerContext::new(&deployment(), &SystemClock, &mut nullifiers),
+        Transcript::new(b"token purchase test"),
+        rand::thread_rng(),
+    );
+    assert!(matches!(result, Err(danake::Error::DeploymentMismatch)));
+}
+
+#[test]
+fn issuers_accept_configured_protocol_versions() {
+    use danake::{wallet::*, EpochParameters, PROTOCOL_VERSION};
+
+    let epoch_params = EpochParameters::from(std::time::Duration::from_secs(86400));
+    let epoch = epoch_params.epoch_at(chrono::Utc::now());
+    let secret = Secrets::new(epoch, rand::thread_rng());
+    let params = Parameters::from(&secret);
+
+    // The protocol version follows the one-byte wire format version.
+    fn with_version(bytes: &[u8], version: u16) -> Vec<u8> {
+        let mut bytes = bytes.to_vec();
+        bytes[1..3].copy_from_slice(&version.to_le_bytes());
+        bytes
+    }
+
+    let (client_state, request) = Wallet::request_issuance(
+        1_000,
+        &params,
+        &deployment(),
+        Transcript::new(b"wallet issuance test"),
+        rand::thread_rng(),
+    );
+    let bytes = request.to_bytes();
+    let issue = |bytes: &[u8], deployment: &danake::DeploymentId| {
+        secret.issue(
+            issuance::Request::from_bytes(bytes).unwrap(),
+            &mut IssuerContext::new(deployment, &SystemClock, &mut MemoryNullifierStore::new()),
+            Transcript::new(b"wallet issuance test"),
+            rand::thread_rng(),
+        )
+    };
+
+    // After a migration to the next version, old clients must upgrade.
+    let migrated = deployment().with_accepted_versions(vec![PROTOCOL_VERSION + 1]);
+    assert!(matches!(
+        issue(&bytes, &migrated),
+        Err(danake::Error::UpgradeRequired(v)) if v == PROTOCOL_VERSION
+    ));
+
+    // Versions newer than any accepted are unsupported.
+    assert!(matches!(
+        issue(&with_version(&bytes, PROTOCOL_VERSION + 1), &deployment()),
+        Err(danake::Error::ProtocolVersion(v)) if v == PROTOCOL_VERSION + 1
+    ));
+
+    // The version is bound to the transcript, so relabelling a request as an
+    // older accepted version makes the proof fail.
+    let window = deployment().with_accepted_versions(vec![PROTOCOL_VERSION - 1, PROTOCOL_VERSION]);
+    assert!(matches!(
+        issue(&with_version(&bytes, PROTOCOL_VERSION - 1), &window),
+        Err(danake::Error::ClientProof)
+    ));
+
+    // During the migration window the current version is still accepted.
+    let response = issue(&bytes, &window).expect("issuance should succeed");
+
+    // With the current and the next version both accepted, only versions
+    // older than both require an upgrade.
+    let next = deployment().with_accepted_versions(vec![PROTOCOL_VERSION, PROTOCOL_VERSION + 1]);
+    assert_eq!(
+        next.accepted_versions().collect::<Vec<_>>(),
+        vec![PROTOCOL_VERSION, PROTOCOL_VERSION + 1]
+    );
+    issue(&bytes, &next).expect("issuance should succeed");
+    assert!(matches!(
+        issue(&with_version(&bytes, PROTOCOL_VERSION - 1), &next),
+        Err(danake::Error::UpgradeRequired(v)) if v == PROTOCOL_VERSION - 1
+    ));
+    assert!(matches!(
+        issue(&with_version(&bytes, PROTOCOL_VERSION + 2), &next),
+        Err(danake::Error::ProtocolVersion(v)) if v == PROTOCOL_VERSION + 2
+    ));
+
+    // The client rejects a response with a different version.
+    let relabelled =
+        issuance::Response::from_bytes(&with_version(&response.to_bytes(), PROTOCOL_VERSION + 1))
+            .unwrap();
+    let stored = client_state.to_bytes();
+    assert!(matches!(
+        client_state.verify_response(relabelled),
+        Err(danake::Error::ProtocolVersion(_))
+    ));
+
+    issuance::AwaitingResponse::from_bytes(&stored, Transcript::new(b"wallet issuance test"))
+        .expect("state should restore")
+        .verify_response(response)
+        .expect("response should verify");
+}
+
+#[test]
+fn secret_types_have_redacted_debug() {
+    use danake::{wallet::*, EpochParameters, MasterSeed};
+
+    let epoch_params = EpochParameters::from(std::time::Duration::from_secs(86400));
+    let epoch = epoch_params.epoch_at(chrono::Utc::now());
+    let seed = MasterSeed::from_bytes([7; 32]);
+    let secret = Secrets::derive(&seed, epoch);
+    let params = Parameters::from(&secret);
+
+    let (client_state, request) = Wallet::request_issuance(
+        1_000,
+        &params,
+        &deployment(),
+        Transcript::new(b"wallet issuance test"),
+        rand::thread_rng(),
+    );
+    assert_eq!(format!("{:?}", client_state), "AwaitingResponse(..)");
+
+    let response = secret
+        .issue(
+            request,
+            &mut IssuerContext::new(
+                &deployment(),
+                &SystemClock,
+                &mut MemoryNullifierStore::new(),
+            ),
+            Transcript::new(b"wallet issuance test"),
+            rand::thread_rng(),
+        )
+        .expect("issuance should succeed");
+    let wallet = client_state
+        .verify_response(response)
+        .expect("response should verify");
+
+    assert_eq!(format!("{:?}", seed), "MasterSeed(..)");
+    for debug in &[format!("{:?}", secret), format!("{:?}", wallet)] {
+        assert!(debug.contains(&format!("{:?}", epoch)));
+        assert!(debug.ends_with(".. }"));
+        assert!(!debug.contains("x_0") && !debug.contains("1000"));
+    }
+}
+
+#[test]
+fn issuer_secrets_survive_export_and_import() {
+    use danake::nullifier::MemoryNullifierStore;
+    use danake::{
+        token, wallet, CredentialType, EpochParameters, KeyFileKey, KeyRing, MasterSeed,
+        RolloverPolicy, SystemClock,
+    };
+
+    let epoch_params = EpochParameters::from(std::time::Duration::from_secs(86400));
+    let now = chrono::Utc::now();
+    let epoch = epoch_params.epoch_at(now);
+    let key = [42; 32];
+
+    // Single-epoch secrets round trip under an external key.
+    let token_secret = token::Secrets::new(epoch, 16, rand::thread_rng());
+    let file = token_secret.to_key_file(KeyFileKey::Key(&key), rand::thread_rng());
+    let imported = token::Secrets::from_key_file(&file, KeyFileKey::Key(&key))
+        .expect("key file should import");
+    assert_eq!(
+        token::Parameters::from(&imported),
+        token::Parameters::from(&token_secret)
+    );
+    assert!(matches!(
+        token::Secrets::from_key_file(&file, KeyFileKey::Key(&[43; 32])),
+        Err(danake::Error::Decryption)
+    ));
+    assert!(matches!(
+        token::Secrets::from_key_file(&file, KeyFileKey::Passphrase("hunter2")),
+        Err(danake::Error::Decryption)
+    ));
+    assert!(matches!(
+        wallet::Secrets::from_key_file(&file, KeyFileKey::Key(&key)),
+        Err(danake::Error::WrongCredentialType(CredentialType::Token))
+    ));
+    let mut tampered = file.clone();
+    *tampered.last_mut().unwrap() ^= 1;
+    assert!(matches!(
+        token::Secrets::from_key_file(&tampered, KeyFileKey::Key(&key)),
+        Err(danake::Error::Decryption)
+    ));
+    tampered[0] = 2;
+    assert!(matches!(
+        token::Secrets::from_key_file(&tampered, KeyFileKey::Key(&key)),
+        Err(danake::Error::KeyFileVersion(2))
+    ));
+
+    // A whole key ring round trips under a passphrase, and still verifies
+    // credentials issued before the export.
+    let policy = RolloverPolicy::new(3);
+    let mut ring = KeyRing::<wallet::Secrets>::from_seed(
+        epoch_params,
+        MasterSeed::generate(rand::thread_rng()),
+    )
+    .with_rollover_policy(policy);
+    ring.update(now, rand::thread_rng());
+    let secret = ring.primary(now).expect("primary secrets exist");
+    let params = wallet::Parameters::from(secret);
+
+    let (client_state, request) = wallet::Wallet::request_issuance(
+        1_000,
+        &params,
+        &deployment(),
+        Transcript::new(b"wallet issuance test"),
+        rand::thread_rng(),
+    );
+    let response = secret
+        .issue(
+            request,
+            &mut IssuerContext::new(
+                &deployment(),
+                &SystemClock,
+                &mut MemoryNullifierStore::new(),
+            ),
+            Transcript::new(b"wallet issuance test"),
+            rand::thread_rng(),
+        )
+        .expect("issuance should succeed");
+    let wallet = client_state
+        .verify_response(response)
+        .expect("response should verify");
+
+    let file = ring.to_key_file(
+        KeyFileKey::Passphrase("correct horse battery staple"),
+        rand::thread_rng(),
+    );
+    assert!(matches!(
+        KeyRing::<wallet::Secrets>::from_key_file(&file, KeyFileKey::Passphrase("hunter2")),
+        Err(danake::Error::Decryption)
+    ));
+    let mut restored = KeyRing::<wallet::Secrets>::from_key_file(
+        &file,
+        KeyFileKey::Passphrase("correct horse battery staple"),
+    )
+    .expect("key file should import");
+    assert_eq!(restored.parameters(), ring.parameters());
+    drop(ring);
+
+    let (_, request) = wallet
+        .request_topup(
+            500,
+            &params,
+            &deployment(),
+            Transcript::new(b"wallet topup test"),
+            rand::thread_rng(),
+        )
+        .expect("epoch is correct");
+    restored
+        .verifying(epoch, now)
+        .expect("restored ring holds the wallet's secrets")
+        .topup(
+            request,
+            &mut IssuerContext::new(
+                &deployment(),
+                &SystemClock,
+                &mut MemoryNullifierStore::new(),
+            ),
+            Transcript::new(b"wallet topup test"),
+            rand::thread_rng(),
+        )
+        .expect("topup should succeed");
+
+    // The seed is restored too, so later epochs derive the same secrets.
+    let later = now + chrono::Duration::days(2);
+    restored.update(later, rand::thread_rng());
+    let later_epoch = epoch_params.epoch_at(later);
+    let later_params = wallet::Parameters::from(restored.primary(later).unwrap());
+    assert_eq!(later_params.epoch(), later_epoch);
+}
+
+#[test]
+fn wallet_rollover_rejects_forged_epochs() {
+    use danake::nullifier::MemoryNullifierStore;
+    use danake::{wallet::*, EpochParameters, SystemClock};
+
+    let epoch_params = EpochParameters::from(std::time::Duration::from_secs(86400));
+    let epoch = epoch_params.epoch_at(chrono::Utc::now());
+    let secret = Secrets::new(epoch, rand::thread_rng());
+    let params = Parameters::from(&secret);
+    let new_secret = Secrets::new(epoch, rand::thread_rng());
+    let new_params = Parameters::from(&new_secret);
+
+    let (client_state, request) = Wallet::request_issuance(
+        1_000,
+        &params,
+        &deployment(),
+        Transcript::new(b"wallet issuance test"),
+        rand::thread_rng(),
+    );
+    let response = secret
+        .issue(
+            request,
+            &mut IssuerContext::new(
+                &deployment(),
+                &SystemClock,
+                &mut MemoryNullifierStore::new(),
+            ),
+            Transcript::new(b"wallet issuance test"),
+            rand::thread_rng(),
+        )
+        .expect("issuance should succeed");
+    let wallet = client_state
+        .verify_response(response)
+        .expect("response should verify");
+
+    let (_, request) = wallet
+        .request_rollover(
+            &params,
+            &new_params,
+            &deployment(),
+            Transcript::new(b"wallet rollover test"),
+            rand::thread_rng(),
+        )
+        .expect("rollover request should succeed");
+    let bytes = request.to_bytes();
+
+    let mut nullifiers = MemoryNullifierStore::new();
+    let mut try_rollover = |bytes: &[u8]| {
+        rollover::Request::from_bytes(bytes).unwrap().rollover(
+            &secret,
+            &new_secret,
+            &mut IssuerContext::new(&deployment(), &SystemClock, &mut nullifiers),
+            Transcript::new(b"wallet rollover test"),
+            rand::thread_rng(),
+        )
+    };
+
+    try_rollover(&bytes).expect("rollover should succeed");
+    assert!(matches!(
+        try_rollover(&bytes),
+        Err(danake::Error::NullifierReuse(_))
+    ));
+
+    // The old epoch follows the wire format version and the protocol
+    // version, as an index and a duration.  An earlier epoch does not let
+    // the same wallet be rolled over again.
+    let mut index = [0u8; 8];
+    index.copy_from_slice(&bytes[3..11]);
+    let mut earlier = bytes.clone();
+    earlier[3..11].copy_from_slice(&(i64::from_le_bytes(index) - 1).to_le_bytes());
+    assert!(matches!(
+        try_rollover(&earlier),
+        Err(danake::Error::WrongEpoch)
+    ));
+    // Epochs with a zero duration would break the epoch arithmetic, so
+    // requests carrying one fail to decode.
+    let mut malformed = bytes.clone();
+    malformed[11..19].copy_from_slice(&0u64.to_le_bytes());
+    assert!(matches!(
+        rollover::Request::from_bytes(&malformed),
+        Err(danake::Error::Encoding)
+    ));
+    let mut new_malformed = bytes;
+    new_malformed[27..35].copy_from_slice(&0u64.to_le_bytes());
+    assert!(matches!(
+        rollover::Request::from_bytes(&new_malformed),
+        Err(danake::Error::Encoding)
+    ));
+}